@@ -0,0 +1,100 @@
+use image::{ImageBuffer, Rgb};
+
+use crate::color::{Color, RGB};
+
+/// Running sum of a pixel's samples along with the variance of their
+/// luminance (Welford's algorithm), used to tell when the pixel converged.
+#[derive(Clone, Default)]
+pub struct PixelStats {
+    pub sum: Color,
+    pub count: usize,
+    mean: f32,
+    m2: f32,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, sample: Color) {
+        self.sum += sample;
+        self.count += 1;
+        let l = sample.luminance();
+        let delta = l - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (l - self.mean);
+    }
+
+    pub fn color(&self) -> Color {
+        if self.count == 0 {
+            return Color::ZERO;
+        }
+        self.sum / self.count as f32
+    }
+
+    /// Variance of the mean luminance, how far the pixel's estimate is
+    /// likely off.
+    pub fn variance_of_mean(&self) -> f32 {
+        if self.count < 2 {
+            return 0.;
+        }
+        self.m2 / ((self.count - 1) * self.count) as f32
+    }
+
+    /// Standard error of the mean luminance relative to the mean itself.
+    /// Dark pixels are measured against a floor so they don't chase noise
+    /// nobody will see.
+    pub fn relative_error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+        let variance = self.m2 / (self.count - 1) as f32;
+        (variance / self.count as f32).sqrt() / self.mean.max(0.01)
+    }
+}
+
+/// Splits this round's `budget` of samples among the pixels that haven't
+/// converged below `threshold`, in proportion to their error. Returns how
+/// many samples each pixel gets, all zero once everything converged.
+pub fn allocate(stats: &[PixelStats], threshold: f32, budget: usize, max: usize) -> Vec<usize> {
+    let errors: Vec<f32> = stats
+        .iter()
+        .map(|s| {
+            let error = s.relative_error();
+            if error > threshold && s.count < max {
+                error.min(1e3)
+            } else {
+                0.
+            }
+        })
+        .collect();
+    let total: f32 = errors.iter().sum();
+    if total <= 0. {
+        return vec![0; stats.len()];
+    }
+
+    errors
+        .iter()
+        .zip(stats)
+        .map(|(error, s)| {
+            if *error <= 0. {
+                return 0;
+            }
+            let n = (budget as f32 * error / total).ceil() as usize;
+            n.min(max - s.count)
+        })
+        .collect()
+}
+
+/// Blue for the fewest samples through to red for the most.
+pub fn heatmap(counts: &[usize], width: usize, height: usize) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let min = counts.iter().cloned().min().unwrap_or(0) as f32;
+    let max = counts.iter().cloned().max().unwrap_or(0) as f32;
+    ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+        let n = counts[x as usize + y as usize * width] as f32;
+        let t = if max > min { (n - min) / (max - min) } else { 0. };
+        let c = Color::new(t, 1. - (2. * t - 1.).abs(), 1. - t);
+        Rgb([
+            (c.r() * 255.) as u8,
+            (c.g() * 255.) as u8,
+            (c.b() * 255.) as u8,
+        ])
+    })
+}
@@ -0,0 +1,234 @@
+use std::{fs, path::Path, sync::Arc};
+
+use glam::{Quat, Vec3};
+
+use crate::{
+    camera::PerspectiveCamera,
+    instance::Instance,
+    material::{ToSurfaceWithMat, WithMat},
+    world::{Hittable, RenderSettings, Surface, World},
+};
+
+pub trait Interpolate: Copy + PartialEq {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self {
+        a + (b - a) * t
+    }
+}
+
+impl Interpolate for Vec3 {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self {
+        a.lerp(b, t)
+    }
+}
+
+impl Interpolate for Quat {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self {
+        a.slerp(b, t)
+    }
+}
+
+/// A list of keyframes sorted by time, linearly interpolated between keys
+/// and held constant before the first and after the last key.
+pub struct Track<T> {
+    keys: Vec<(f32, T)>,
+}
+
+impl<T> Track<T>
+where
+    T: Interpolate,
+{
+    pub fn constant(value: T) -> Self {
+        Self {
+            keys: vec![(0., value)],
+        }
+    }
+
+    pub fn key(mut self, time: f32, value: T) -> Self {
+        let idx = self.keys.partition_point(|(t, _)| *t <= time);
+        self.keys.insert(idx, (time, value));
+        self
+    }
+
+    pub fn sample(&self, time: f32) -> T {
+        let idx = self.keys.partition_point(|(t, _)| *t <= time);
+        if idx == 0 {
+            return self.keys[0].1;
+        }
+        if idx == self.keys.len() {
+            return self.keys[idx - 1].1;
+        }
+        let (t0, a) = self.keys[idx - 1];
+        let (t1, b) = self.keys[idx];
+        T::interpolate(a, b, (time - t0) / (t1 - t0))
+    }
+
+    pub fn changed(&self, from: f32, to: f32) -> bool {
+        self.sample(from) != self.sample(to)
+            || self.keys.iter().any(|(t, _)| *t > from && *t < to)
+    }
+}
+
+pub struct CameraTrack {
+    pub origin: Track<Vec3>,
+    pub lookat: Track<Vec3>,
+    pub vfov: Track<f32>,
+    pub aperture: Track<f32>,
+    pub focus_dist: Track<f32>,
+    pub autofocus: bool,
+}
+
+impl CameraTrack {
+    pub fn new(origin: Track<Vec3>, lookat: Track<Vec3>, vfov: Track<f32>) -> Self {
+        Self {
+            origin,
+            lookat,
+            vfov,
+            aperture: Track::constant(0.),
+            focus_dist: Track::constant(10.),
+            autofocus: false,
+        }
+    }
+
+    pub fn fixed(origin: Vec3, lookat: Vec3, vfov: f32) -> Self {
+        Self::new(
+            Track::constant(origin),
+            Track::constant(lookat),
+            Track::constant(vfov),
+        )
+    }
+
+    pub fn camera(&self, time: f32, aspect_ratio: f32, world: &World) -> PerspectiveCamera {
+        let mut camera = PerspectiveCamera::new(
+            self.origin.sample(time),
+            self.lookat.sample(time),
+            Vec3::Y,
+            self.vfov.sample(time),
+            aspect_ratio,
+            self.aperture.sample(time),
+            self.focus_dist.sample(time),
+        );
+        if self.autofocus {
+            camera.autofocus(world);
+        }
+        camera
+    }
+}
+
+pub trait ObjectTrack {
+    fn changed(&self, from: f32, to: f32) -> bool;
+    fn apply(&self, time: f32, objs: &mut Vec<WithMat>);
+}
+
+/// Drives the transform of `world.objs[idx]` by rebuilding it as an
+/// `Instance` of `obj`, keeping whatever material it already has.
+pub struct InstanceTrack<T> {
+    pub idx: usize,
+    pub obj: Arc<T>,
+    pub translation: Track<Vec3>,
+    pub rotation: Track<Quat>,
+    pub scale: Track<Vec3>,
+}
+
+impl<T> InstanceTrack<T> {
+    pub fn new(
+        idx: usize,
+        obj: Arc<T>,
+        translation: Track<Vec3>,
+        rotation: Track<Quat>,
+        scale: Track<Vec3>,
+    ) -> Self {
+        Self {
+            idx,
+            obj,
+            translation,
+            rotation,
+            scale,
+        }
+    }
+}
+
+impl<T> ObjectTrack for InstanceTrack<T>
+where
+    T: Hittable + Surface + 'static,
+{
+    fn changed(&self, from: f32, to: f32) -> bool {
+        self.translation.changed(from, to)
+            || self.rotation.changed(from, to)
+            || self.scale.changed(from, to)
+    }
+
+    fn apply(&self, time: f32, objs: &mut Vec<WithMat>) {
+        let instance = Instance::from_trs(
+            self.obj.clone(),
+            self.translation.sample(time),
+            self.rotation.sample(time),
+            self.scale.sample(time),
+        );
+        let mat = objs[self.idx].mat.clone();
+        objs[self.idx] = instance.with_surface_mat(mat);
+    }
+}
+
+pub struct Animation {
+    pub camera: CameraTrack,
+    pub objects: Vec<Box<dyn ObjectTrack>>,
+    pub fps: f32,
+    pub frames: usize,
+}
+
+impl Animation {
+    pub fn new(camera: CameraTrack, fps: f32, frames: usize) -> Self {
+        Self {
+            camera,
+            objects: vec![],
+            fps,
+            frames,
+        }
+    }
+
+    pub fn time(&self, frame: usize) -> f32 {
+        frame as f32 / self.fps
+    }
+
+    /// Renders `frame_0001.png`, `frame_0002.png`, ... into `dir`. Objects are
+    /// only re-instanced, and the BVH only rebuilt, when a track changed
+    /// since the previous frame.
+    pub fn render(
+        &self,
+        world: &mut World,
+        dir: &str,
+        settings: &RenderSettings,
+        aspect_ratio: f32,
+    ) {
+        fs::create_dir_all(dir).expect("Output directory to be created");
+
+        let mut last_time = None;
+        for frame in 0..self.frames {
+            let time = self.time(frame);
+            let mut dirty = false;
+            for track in &self.objects {
+                if last_time.map_or(true, |last| track.changed(last, time)) {
+                    track.apply(time, &mut world.objs);
+                    dirty = true;
+                }
+            }
+            if dirty {
+                world.build();
+            }
+            last_time = Some(time);
+
+            let path = Path::new(dir).join(format!("frame_{:04}.png", frame + 1));
+            println!("Frame {}/{}", frame + 1, self.frames);
+            let camera = self.camera.camera(time, aspect_ratio, world);
+            world.render(
+                path.to_str().expect("Frame path to be valid UTF-8"),
+                &camera,
+                settings,
+            );
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use bvh::ray::Ray;
+
+use crate::{
+    color::Color,
+    integrator::Integrator,
+    lpe::{Lobe, LobeColors},
+    orthonormalbasis::OrthoNormalBasis,
+    rand_cos_dir,
+    world::World,
+};
+
+/// Fraction of the hemisphere above the first hit that is open within
+/// `max_distance`, cosine weighted, from `samples` rays. Rays that hit
+/// nothing count as fully open.
+pub struct AmbientOcclusion {
+    pub samples: usize,
+    pub max_distance: f32,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn li(&self, world: &World, ray: &Ray, _max_depth: usize) -> LobeColors {
+        let mut color = LobeColors::default();
+        let (_, intersection) = match world.first_intersection(*ray, 0.00001, f32::INFINITY) {
+            Some(hit) => hit,
+            None => {
+                color.add(Lobe::DirectDiffuse, Color::ONE);
+                return color;
+            }
+        };
+        let p = ray.at(intersection.distance);
+        let uvw = OrthoNormalBasis::from_w(&intersection.norm);
+        let samples = self.samples.max(1);
+        let open = (0..samples)
+            .filter(|_| {
+                let dir = uvw.local(&rand_cos_dir());
+                !world.occluded(Ray::new(p, dir), self.max_distance)
+            })
+            .count();
+        color.add(
+            Lobe::DirectDiffuse,
+            Color::splat(open as f32 / samples as f32),
+        );
+        color
+    }
+}
@@ -0,0 +1,217 @@
+use glam::Vec3;
+use image::{ImageBuffer, Rgb};
+use rayon::prelude::*;
+
+use crate::{
+    camera::Camera,
+    color::{Color, RGB},
+    material::Material,
+    random,
+    sampler::{self, mix},
+    world::{RenderSettings, World},
+};
+
+/// Auxiliary render passes, all taken at the first surface a camera ray hits.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aov {
+    Albedo,
+    /// Shading normal, facing the camera
+    Normal,
+    /// Distance along the camera ray
+    Depth,
+    Position,
+    ObjectId,
+    MaterialId,
+}
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+}
+
+/// Every AOV for a whole image. Albedo, normal, depth and position are
+/// averaged over jittered samples so edges are antialiased like the beauty
+/// image, IDs can't be averaged and come from the ray through the pixel
+/// center.
+pub struct AovBuffers {
+    pub width: usize,
+    pub height: usize,
+    /// Misses show the background
+    pub albedo: Vec<Color>,
+    /// Zero where nothing was hit
+    pub normal: Vec<Vec3>,
+    /// Infinite where nothing was hit
+    pub depth: Vec<f32>,
+    pub position: Vec<Vec3>,
+    pub object_id: Vec<Option<usize>>,
+    pub material_id: Vec<Option<usize>>,
+}
+
+#[derive(Clone, Default)]
+struct AovPixel {
+    albedo: Color,
+    normal: Vec3,
+    depth: f32,
+    position: Vec3,
+    object_id: Option<usize>,
+    material_id: Option<usize>,
+}
+
+impl AovBuffers {
+    pub fn render(
+        world: &World,
+        camera: &dyn Camera,
+        settings: &RenderSettings,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        let samples = settings.aov_samples.max(1);
+        let pixels: Vec<AovPixel> = (0..height)
+            .into_par_iter()
+            .flat_map_iter(|row| {
+                // Kept apart from the beauty samples so AOVs don't change
+                // its noise
+                sampler::set_sampler(settings.sampler.build(mix(settings.seed), samples, width));
+                let y = (height - 1) - row;
+                (0..width)
+                    .map(|x| world.aov_pixel(camera, x, y, row * width + x, width, height, samples))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            albedo: pixels.iter().map(|px| px.albedo).collect(),
+            normal: pixels.iter().map(|px| px.normal).collect(),
+            depth: pixels.iter().map(|px| px.depth).collect(),
+            position: pixels.iter().map(|px| px.position).collect(),
+            object_id: pixels.iter().map(|px| px.object_id).collect(),
+            material_id: pixels.iter().map(|px| px.material_id).collect(),
+        }
+    }
+
+    /// 8 bit image of `aov`. Albedo gets the same gamma as the beauty image,
+    /// normals are mapped from [-1, 1], depth and position are stretched
+    /// over the range found in the image and IDs get arbitrary colors.
+    pub fn image(&self, aov: Aov) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        let (min_depth, max_depth) = self
+            .depth
+            .iter()
+            .filter(|d| d.is_finite())
+            .fold((f32::INFINITY, 0f32), |(lo, hi), &d| (lo.min(d), hi.max(d)));
+        let (min_pos, max_pos) = self
+            .position
+            .iter()
+            .zip(&self.depth)
+            .filter(|(_, d)| d.is_finite())
+            .fold(
+                (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+                |(lo, hi), (&p, _)| (lo.min(p), hi.max(p)),
+            );
+
+        ImageBuffer::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let i = x as usize + y as usize * self.width;
+            let linear = match aov {
+                Aov::Albedo => return self.albedo[i].to_px(1),
+                Aov::Normal => (self.normal[i] + 1.) * 0.5,
+                Aov::Depth => {
+                    let d = self.depth[i];
+                    if !d.is_finite() {
+                        Vec3::ZERO
+                    } else if max_depth > min_depth {
+                        Vec3::splat(1. - (d - min_depth) / (max_depth - min_depth))
+                    } else {
+                        Vec3::ONE
+                    }
+                }
+                Aov::Position => {
+                    if !self.depth[i].is_finite() {
+                        Vec3::ZERO
+                    } else {
+                        (self.position[i] - min_pos) / (max_pos - min_pos).max(Vec3::splat(1e-6))
+                    }
+                }
+                Aov::ObjectId => id_color(self.object_id[i]),
+                Aov::MaterialId => id_color(self.material_id[i]),
+            };
+            let c = linear.clamp(Vec3::ZERO, Vec3::ONE);
+            Rgb([
+                (c.x * 255.9999) as u8,
+                (c.y * 255.9999) as u8,
+                (c.z * 255.9999) as u8,
+            ])
+        })
+    }
+}
+
+fn id_color(id: Option<usize>) -> Vec3 {
+    match id {
+        Some(id) => {
+            let h = mix(id as u64 + 1);
+            Vec3::new(
+                (h & 0xff) as f32 / 255.,
+                ((h >> 8) & 0xff) as f32 / 255.,
+                ((h >> 16) & 0xff) as f32 / 255.,
+            )
+        }
+        None => Vec3::ZERO,
+    }
+}
+
+impl World {
+    #[allow(clippy::too_many_arguments)]
+    fn aov_pixel(
+        &self,
+        camera: &dyn Camera,
+        x: usize,
+        y: usize,
+        i: usize,
+        width: usize,
+        height: usize,
+        samples: usize,
+    ) -> AovPixel {
+        let mut px = AovPixel::default();
+        let mut hits = 0;
+        for s in 0..samples {
+            sampler::start_sample(i, s);
+            // The first sample goes through the center, for the IDs
+            let (jx, jy) = if s == 0 { (0.5, 0.5) } else { (random(), random()) };
+            let u = (x as f32 + jx) / width as f32;
+            let v = (y as f32 + jy) / height as f32;
+            let ray = camera.get_ray(u, v);
+            match self.first_intersection(ray, 0.00001, f32::INFINITY) {
+                Some((obj, intersection)) => {
+                    px.albedo += obj.albedo(&ray, &intersection);
+                    px.normal += intersection.norm;
+                    px.depth += intersection.distance;
+                    px.position += ray.at(intersection.distance);
+                    hits += 1;
+                    if s == 0 {
+                        px.object_id = Some(obj.object_id);
+                        px.material_id = Some(obj.material_id);
+                    }
+                }
+                None => px.albedo += self.background.value(ray.direction).min(Color::ONE),
+            }
+        }
+
+        px.albedo /= samples as f32;
+        if hits > 0 {
+            px.normal = px.normal.normalize_or_zero();
+            px.depth /= hits as f32;
+            px.position /= hits as f32;
+        } else {
+            px.depth = f32::INFINITY;
+        }
+        px
+    }
+}
@@ -0,0 +1,145 @@
+use std::{f32::consts::PI, fs::File, io::BufReader};
+
+use glam::{Quat, Vec2, Vec3};
+use image::codecs::hdr::HdrDecoder;
+
+use crate::{
+    color::{Color, RGB},
+    distribution::Distribution2D,
+    random,
+};
+
+/// Radiance arriving from infinitely far away, seen by rays that leave the
+/// scene.
+pub trait Background: Sync + Send {
+    fn value(&self, dir: Vec3) -> Color;
+
+    /// Picks a direction to send a shadow ray towards, returning it with its
+    /// solid angle pdf. Backgrounds that can't be sampled return `None` and
+    /// are only found by chance.
+    fn sample(&self) -> Option<(Vec3, f32)> {
+        None
+    }
+
+    fn pdf(&self, _dir: Vec3) -> f32 {
+        0.
+    }
+}
+
+impl Background for Color {
+    fn value(&self, _dir: Vec3) -> Color {
+        *self
+    }
+}
+
+/// Equirectangular (latitude/longitude) environment map. `+Y` is up and the
+/// center of the image looks down `+X`.
+pub struct EnvMap {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+    pub rotation: Quat,
+    pub intensity: f32,
+    distribution: Distribution2D,
+}
+
+impl EnvMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        // Weight by sin(theta) since rows near the poles cover less solid angle
+        let weights: Vec<f32> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let theta = ((i / width) as f32 + 0.5) / height as f32 * PI;
+                c.luminance() * theta.sin()
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, width, height);
+        Self {
+            width,
+            height,
+            pixels,
+            rotation: Quat::IDENTITY,
+            intensity: 1.,
+            distribution,
+        }
+    }
+
+    /// Loads a Radiance `.hdr` file as is, any other image format is treated
+    /// as gamma 2 encoded.
+    pub fn from_file(path: &str) -> Self {
+        if path.ends_with(".hdr") {
+            let input = BufReader::new(File::open(path).unwrap());
+            let decoder = HdrDecoder::new(input).expect("HDR header to be valid");
+            let meta = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()
+                .expect("HDR image to decode")
+                .iter()
+                .map(|px| Color::new(px[0], px[1], px[2]))
+                .collect();
+            Self::new(meta.width as usize, meta.height as usize, pixels)
+        } else {
+            let image = image::open(path).expect("Image to load").to_rgb8();
+            let pixels = image
+                .pixels()
+                .map(|px| {
+                    let c = Color::new(px[0] as f32, px[1] as f32, px[2] as f32) / 255.;
+                    c * c
+                })
+                .collect();
+            Self::new(image.width() as usize, image.height() as usize, pixels)
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: Quat) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    fn dir_to_uv(&self, dir: Vec3) -> Vec2 {
+        let d = (self.rotation.inverse() * dir).normalize();
+        let phi = d.z.atan2(d.x);
+        let theta = d.y.clamp(-1., 1.).acos();
+        Vec2::new((phi + PI) / (2. * PI), theta / PI)
+    }
+
+    fn uv_to_dir(&self, uv: Vec2) -> Vec3 {
+        let phi = uv.x * 2. * PI - PI;
+        let theta = uv.y * PI;
+        let d = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+        self.rotation * d
+    }
+}
+
+impl Background for EnvMap {
+    fn value(&self, dir: Vec3) -> Color {
+        let uv = self.dir_to_uv(dir);
+        let x = ((uv.x * self.width as f32) as usize).min(self.width - 1);
+        let y = ((uv.y * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[x + y * self.width] * self.intensity
+    }
+
+    fn sample(&self) -> Option<(Vec3, f32)> {
+        let (uv, pdf) = self.distribution.sample(random(), random());
+        let sin_theta = (uv.y * PI).sin();
+        if pdf == 0. || sin_theta == 0. {
+            return None;
+        }
+        Some((self.uv_to_dir(uv), pdf / (2. * PI * PI * sin_theta)))
+    }
+
+    fn pdf(&self, dir: Vec3) -> f32 {
+        let uv = self.dir_to_uv(dir);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta == 0. {
+            return 0.;
+        }
+        self.distribution.pdf(uv) / (2. * PI * PI * sin_theta)
+    }
+}
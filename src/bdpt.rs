@@ -0,0 +1,586 @@
+use std::f32::consts::PI;
+
+use bvh::ray::{Intersection, Ray};
+use glam::Vec3;
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    integrator::{Integrator, Splat},
+    lpe::{Event, Lobe, LobeColors},
+    material::{Material, WithMat},
+    orthonormalbasis::OrthoNormalBasis,
+    power_heuristic, rand_cos_dir, random, stats,
+    world::{SurfaceSample, World},
+};
+
+/// Bidirectional path tracer after Veach's thesis, laid out like pbrt's.
+///
+/// Every sample traces one subpath from the camera and one from a point on
+/// an emitter, then joins every prefix of one to every prefix of the other.
+/// The strategies are combined with the power heuristic, so caustics seen
+/// through glass and rooms lit around a corner converge far faster than
+/// with `PathTracer`. Paths straight from the lights into the camera come
+/// back as splats, which needs a pinhole `PerspectiveCamera`.
+///
+/// Only emissive objects start light subpaths. Delta lights and the
+/// background are sampled from camera subpath vertices alone, like the path
+/// tracer does.
+pub struct Bdpt;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Camera,
+    /// Start of a light subpath, on an emitter
+    Light,
+    Surface,
+}
+
+#[derive(Clone, Copy)]
+struct Vertex<'a> {
+    kind: Kind,
+    p: Vec3,
+    /// Faces the side the subpath arrived from, outward on light vertices,
+    /// zero on the camera
+    n: Vec3,
+    back_face: bool,
+    u: f32,
+    v: f32,
+    obj: Option<&'a WithMat>,
+    /// Throughput of the subpath up to and including this vertex
+    beta: Color,
+    /// Scatters specularly, so nothing can connect to it
+    delta: bool,
+    /// Area pdf of this vertex, sampled from the one before it
+    pdf_fwd: f32,
+    /// Area pdf of this vertex if the subpath ran the other way
+    pdf_rev: f32,
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(ray: &Ray, delta: bool) -> Self {
+        Self {
+            kind: Kind::Camera,
+            p: ray.origin,
+            n: Vec3::ZERO,
+            back_face: false,
+            u: 0.,
+            v: 0.,
+            obj: None,
+            beta: Color::ONE,
+            delta,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+        }
+    }
+
+    fn light(obj: &'a WithMat, sample: &SurfaceSample) -> Self {
+        Self {
+            kind: Kind::Light,
+            p: sample.p,
+            n: sample.n,
+            back_face: false,
+            u: sample.u,
+            v: sample.v,
+            obj: Some(obj),
+            beta: Color::ONE / sample.pdf,
+            delta: false,
+            pdf_fwd: sample.pdf,
+            pdf_rev: 0.,
+        }
+    }
+
+    fn surface(ray: &Ray, obj: &'a WithMat, intersection: &Intersection, beta: Color) -> Self {
+        Self {
+            kind: Kind::Surface,
+            p: ray.at(intersection.distance),
+            n: intersection.norm,
+            back_face: intersection.back_face,
+            u: intersection.u,
+            v: intersection.v,
+            obj: Some(obj),
+            beta,
+            delta: false,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+        }
+    }
+
+    fn on_surface(&self) -> bool {
+        self.kind != Kind::Camera
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.obj.map_or(false, |obj| obj.light_pmf > 0.)
+    }
+
+    /// The vertex as a hit by a ray arriving from `dir`, so materials can
+    /// be asked about it.
+    fn query(&self, dir: Vec3) -> (Ray, Intersection) {
+        let (norm, back_face) = if self.n.dot(dir) >= 0. {
+            (self.n, self.back_face)
+        } else {
+            (-self.n, !self.back_face)
+        };
+        (
+            Ray::new(self.p + dir, -dir),
+            Intersection::new(1., self.u, self.v, norm, back_face),
+        )
+    }
+
+    /// Light emitted towards `dir`.
+    fn le(&self, dir: Vec3) -> Color {
+        let (ray, intersection) = self.query(dir);
+        self.obj
+            .map_or(Color::ZERO, |obj| obj.emit(&ray, &intersection))
+    }
+
+    /// BSDF times the cosine towards `to`, between the directions `from`
+    /// and `to`.
+    fn f(&self, from: Vec3, to: Vec3) -> Color {
+        let (ray, intersection) = self.query(from);
+        self.obj.map_or(Color::ZERO, |obj| {
+            obj.eval(&ray, &intersection, &Ray::new(self.p, to))
+        })
+    }
+
+    /// Area pdf of sampling `next` from this vertex, reached from `prev`.
+    fn pdf(&self, camera: Option<&dyn Camera>, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        let dir = (next.p - self.p).normalize();
+        let pdf = match self.kind {
+            Kind::Camera => camera
+                .and_then(|camera| camera.importance(next.p))
+                .map_or(0., |importance| importance.pdf),
+            Kind::Light => emission_pdf(self.n, dir),
+            Kind::Surface => match (prev, self.obj) {
+                (Some(prev), Some(obj)) => {
+                    let (ray, intersection) = self.query((prev.p - self.p).normalize());
+                    obj.scattering_pdf(&ray, &intersection, &Ray::new(self.p, dir))
+                }
+                _ => 0.,
+            },
+        };
+        to_area(pdf, self.p, next)
+    }
+
+    /// Area pdf of `next` if this vertex, on an emitter, had started the
+    /// light subpath.
+    fn pdf_light(&self, next: &Vertex) -> f32 {
+        to_area(emission_pdf(self.n, (next.p - self.p).normalize()), self.p, next)
+    }
+
+    /// Area pdf of a light subpath starting at this vertex.
+    fn pdf_light_origin(&self) -> f32 {
+        self.obj
+            .map_or(0., |obj| obj.light_pmf * obj.surface.surface_pdf(self.p, self.n))
+    }
+}
+
+/// Solid angle pdf of `emission_dir` picking `dir`.
+pub(crate) fn emission_pdf(n: Vec3, dir: Vec3) -> f32 {
+    n.dot(dir).abs() / (2. * PI)
+}
+
+/// Cosine weighted direction leaving either side of a surface with normal
+/// `n`, since emitters can be two sided.
+pub(crate) fn emission_dir(n: Vec3) -> Vec3 {
+    let side = if random() < 0.5 { n } else { -n };
+    OrthoNormalBasis::from_w(&side).local(&rand_cos_dir())
+}
+
+/// Converts a solid angle pdf at `p` into an area pdf at `next`.
+fn to_area(pdf: f32, p: Vec3, next: &Vertex) -> f32 {
+    let to_next = next.p - p;
+    let dist2 = to_next.length_squared();
+    if dist2 == 0. {
+        return 0.;
+    }
+    let mut pdf = pdf / dist2;
+    if next.on_surface() {
+        pdf *= next.n.dot(to_next / dist2.sqrt()).abs();
+    }
+    pdf
+}
+
+/// Where a camera subpath left the scene.
+struct Escape {
+    dir: Vec3,
+    beta: Color,
+    /// Solid angle pdf of the direction, None after specular bounces
+    pdf: Option<f32>,
+}
+
+/// Extends `path` from its last vertex along `ray` by up to `max_vertices`
+/// surface hits. `pdf` is the solid angle pdf of `ray`.
+fn random_walk<'a>(
+    world: &'a World,
+    mut ray: Ray,
+    mut beta: Color,
+    mut pdf: f32,
+    max_vertices: usize,
+    path: &mut Vec<Vertex<'a>>,
+) -> Option<Escape> {
+    for _ in 0..max_vertices {
+        let (obj, intersection) = match world.first_intersection(ray, 0.00001, f32::INFINITY) {
+            Some(hit) => hit,
+            None => {
+                let last = path[path.len() - 1];
+                let pdf = if last.kind == Kind::Camera || last.delta {
+                    None
+                } else {
+                    Some(pdf)
+                };
+                return Some(Escape {
+                    dir: ray.direction,
+                    beta,
+                    pdf,
+                });
+            }
+        };
+        let mut vertex = Vertex::surface(&ray, obj, &intersection, beta);
+        vertex.pdf_fwd = to_area(pdf, path[path.len() - 1].p, &vertex);
+        path.push(vertex);
+
+        let (child_ray, attenuation, scatter_pdf) = match obj.scatter(&ray, &intersection) {
+            Some(scattered) if scattered.2 > 0. => scattered,
+            _ => break,
+        };
+        beta *= attenuation * obj.scattering_pdf(&ray, &intersection, &child_ray) / scatter_pdf;
+
+        let n = path.len();
+        // Specular bounces get a pdf of zero both ways, which the MIS weight
+        // knows to skip
+        let rev = if obj.is_specular() {
+            path[n - 1].delta = true;
+            pdf = 0.;
+            0.
+        } else {
+            pdf = scatter_pdf;
+            let (back, back_hit) = path[n - 1].query(child_ray.direction);
+            obj.scattering_pdf(&back, &back_hit, &Ray::new(path[n - 1].p, -ray.direction))
+        };
+        path[n - 2].pdf_rev = to_area(rev, path[n - 1].p, &path[n - 2]);
+        ray = child_ray;
+    }
+    None
+}
+
+/// What the camera subpath did at its first surface.
+fn first_event(camera_path: &[Vertex]) -> Option<Event> {
+    let first = camera_path.get(1)?;
+    if !first.delta {
+        return Some(Event::Diffuse);
+    }
+    let next = camera_path.get(2)?;
+    if (next.p - first.p).dot(first.n) < 0. {
+        Some(Event::Transmission)
+    } else {
+        Some(Event::Specular)
+    }
+}
+
+/// Weight of the strategy joining the first `s` light vertices to the first
+/// `t` camera vertices, against every other way of sampling the same path.
+/// `sampled` stands in for the light vertex when `s` is 1 and it was picked
+/// just for the connection.
+fn mis_weight(
+    camera: Option<&dyn Camera>,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    sampled: Option<&Vertex>,
+    s: usize,
+    t: usize,
+) -> f32 {
+    if s + t == 2 {
+        return 1.;
+    }
+    let qs = match (s, sampled) {
+        (0, _) => None,
+        (1, Some(sampled)) => Some(sampled),
+        _ => Some(&light_path[s - 1]),
+    };
+    let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+    let pt = &camera_path[t - 1];
+    let pt_minus = if t > 1 { Some(&camera_path[t - 2]) } else { None };
+
+    // Reverse pdfs of the vertices around the connection, which depend on
+    // the strategy
+    let pt_rev = match qs {
+        Some(qs) => qs.pdf(camera, qs_minus, pt),
+        None => pt.pdf_light_origin(),
+    };
+    let pt_minus_rev = pt_minus.map_or(0., |pt_minus| match qs {
+        Some(qs) => pt.pdf(camera, Some(qs), pt_minus),
+        None => pt.pdf_light(pt_minus),
+    });
+    let qs_rev = qs.map_or(0., |qs| pt.pdf(camera, pt_minus, qs));
+    let qs_minus_rev = match (qs, qs_minus) {
+        (Some(qs), Some(qs_minus)) => qs.pdf(camera, Some(pt), qs_minus),
+        _ => 0.,
+    };
+
+    let remap = |pdf: f32| if pdf != 0. { pdf } else { 1. };
+    let mut sum = 0.;
+
+    let mut ri = 1.;
+    for i in (1..t).rev() {
+        let vertex = &camera_path[i];
+        let pdf_rev = if i == t - 1 {
+            pt_rev
+        } else if i + 2 == t {
+            pt_minus_rev
+        } else {
+            vertex.pdf_rev
+        };
+        ri *= remap(pdf_rev) / remap(vertex.pdf_fwd);
+        let delta = i != t - 1 && vertex.delta;
+        if !delta && !camera_path[i - 1].delta {
+            sum += ri * ri;
+        }
+    }
+
+    let mut ri = 1.;
+    for i in (0..s).rev() {
+        let (vertex, pdf_rev) = if i == s - 1 {
+            (qs.unwrap(), qs_rev)
+        } else if i + 2 == s {
+            (&light_path[i], qs_minus_rev)
+        } else {
+            (&light_path[i], light_path[i].pdf_rev)
+        };
+        ri *= remap(pdf_rev) / remap(vertex.pdf_fwd);
+        let delta = i != s - 1 && vertex.delta;
+        let delta_before = i > 0 && light_path[i - 1].delta;
+        if !delta && !delta_before {
+            sum += ri * ri;
+        }
+    }
+
+    1. / (1. + sum)
+}
+
+impl Bdpt {
+    fn trace(
+        &self,
+        world: &World,
+        camera: Option<&dyn Camera>,
+        ray: &Ray,
+        max_depth: usize,
+        splats: &mut Vec<Splat>,
+    ) -> LobeColors {
+        let mut color = LobeColors::default();
+        if max_depth == 0 {
+            return color;
+        }
+
+        // Lens cameras can't be hit by light subpaths, so they count as a
+        // delta vertex and the strategies ending on them drop out
+        let camera_pdf = camera
+            .and_then(|camera| camera.importance(ray.origin + ray.direction))
+            .map(|importance| importance.pdf);
+        let mut camera_path = vec![Vertex::camera(ray, camera_pdf.is_none())];
+        let escape = random_walk(
+            world,
+            *ray,
+            Color::ONE,
+            camera_pdf.unwrap_or(0.),
+            max_depth,
+            &mut camera_path,
+        );
+        let first = first_event(&camera_path);
+
+        let mut light_path = vec![];
+        if let Some((obj, sample)) = world.sample_emitter() {
+            let n = sample.n;
+            let dir = emission_dir(n);
+            let cos = n.dot(dir).abs();
+            let origin = Vertex::light(obj, &sample);
+            let pdf_dir = emission_pdf(n, dir);
+            let beta = origin.beta * origin.le(dir) * cos / pdf_dir;
+            light_path.push(origin);
+            if pdf_dir > 0. && beta != Color::ZERO {
+                random_walk(
+                    world,
+                    Ray::new(sample.p, dir),
+                    beta,
+                    pdf_dir,
+                    max_depth - 1,
+                    &mut light_path,
+                );
+            }
+        }
+
+        // The background and delta lights, from the camera side only
+        for t in 2..=camera_path.len() {
+            let vertex = &camera_path[t - 1];
+            if vertex.delta {
+                continue;
+            }
+            let obj = vertex.obj.unwrap();
+            let (ray, intersection) = vertex.query((camera_path[t - 2].p - vertex.p).normalize());
+            let attenuation = obj.albedo(&ray, &intersection);
+            let direct = world.sample_background(&ray, &intersection, obj, attenuation, true)
+                + world.sample_lights(&ray, &intersection, obj, attenuation);
+            color.add(Lobe::of(first, t - 1), vertex.beta * direct);
+        }
+        if let Some(escape) = escape {
+            let weight = match escape.pdf {
+                Some(pdf) => power_heuristic(pdf, world.background.pdf(escape.dir)),
+                None => 1.,
+            };
+            color.add(
+                Lobe::of(first, camera_path.len() - 1),
+                escape.beta * weight * world.background.value(escape.dir),
+            );
+        }
+
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || s + t - 1 > max_depth {
+                    continue;
+                }
+                if t == 1 {
+                    if let Some(camera) = camera {
+                        if let Some(splat) = connect_to_camera(world, camera, &light_path, s) {
+                            splats.push(splat);
+                        }
+                    }
+                    continue;
+                }
+                let contribution = connect(world, camera, &light_path, &camera_path, s, t);
+                if contribution != Color::ZERO {
+                    let first = if s > 0 && t == 2 {
+                        Some(Event::Diffuse)
+                    } else {
+                        first
+                    };
+                    color.add(Lobe::of(first, s + t - 2), contribution);
+                }
+            }
+        }
+
+        stats::count_path(camera_path.len() - 1);
+        color
+    }
+}
+
+/// Joins the first `s` light vertices to the first `t` camera vertices,
+/// for `t` of at least 2, returning the weighted contribution.
+fn connect(
+    world: &World,
+    camera: Option<&dyn Camera>,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+) -> Color {
+    let pt = &camera_path[t - 1];
+    let to_prev = (camera_path[t - 2].p - pt.p).normalize();
+
+    if s == 0 {
+        if !pt.is_emitter() {
+            return Color::ZERO;
+        }
+        let contribution = pt.beta * pt.le(to_prev);
+        if contribution == Color::ZERO {
+            return contribution;
+        }
+        return contribution * mis_weight(camera, light_path, camera_path, None, s, t);
+    }
+    if pt.delta {
+        return Color::ZERO;
+    }
+
+    let sampled;
+    let qs = if s == 1 {
+        let (obj, sample) = match world.sample_emitter() {
+            Some(sample) => sample,
+            None => return Color::ZERO,
+        };
+        sampled = Vertex::light(obj, &sample);
+        &sampled
+    } else {
+        &light_path[s - 1]
+    };
+    if qs.delta {
+        return Color::ZERO;
+    }
+
+    let to_qs = qs.p - pt.p;
+    let dist2 = to_qs.length_squared();
+    let dist = dist2.sqrt();
+    let dir = to_qs / dist;
+    let at_qs = if s == 1 {
+        qs.le(-dir) * qs.n.dot(dir).abs()
+    } else {
+        let qs_prev = (light_path[s - 2].p - qs.p).normalize();
+        qs.f(qs_prev, -dir)
+    };
+    let contribution = pt.beta * pt.f(to_prev, dir) * qs.beta * at_qs / dist2;
+    if contribution == Color::ZERO || world.occluded(Ray::new(pt.p, dir), dist * 0.999) {
+        return Color::ZERO;
+    }
+    let sampled = if s == 1 { Some(qs) } else { None };
+    contribution * mis_weight(camera, light_path, camera_path, sampled, s, t)
+}
+
+/// Joins the first `s` light vertices straight to the camera.
+fn connect_to_camera(
+    world: &World,
+    camera: &dyn Camera,
+    light_path: &[Vertex],
+    s: usize,
+) -> Option<Splat> {
+    let qs = &light_path[s - 1];
+    if qs.delta {
+        return None;
+    }
+    let importance = camera.importance(qs.p)?;
+    let to_camera = importance.origin - qs.p;
+    let dist2 = to_camera.length_squared();
+    let dist = dist2.sqrt();
+    let dir = to_camera / dist;
+    let at_qs = if s == 1 {
+        qs.le(dir) * qs.n.dot(dir).abs()
+    } else {
+        qs.f((light_path[s - 2].p - qs.p).normalize(), dir)
+    };
+    let color = qs.beta * at_qs * importance.we * importance.cos / dist2;
+    if color == Color::ZERO || world.occluded(Ray::new(qs.p, dir), dist * 0.999) {
+        return None;
+    }
+
+    let camera_vertex = Vertex::camera(&Ray::new(importance.origin, -dir), false);
+    let weight = mis_weight(Some(camera), light_path, &[camera_vertex], None, s, 1);
+    // Seen from the camera the light scatters diffusely at `qs` first
+    let lobe = if s == 1 {
+        Lobe::Emission
+    } else {
+        Lobe::of(Some(Event::Diffuse), s - 1)
+    };
+    Some(Splat {
+        u: importance.u,
+        v: importance.v,
+        color: color * weight,
+        lobe,
+    })
+}
+
+impl Integrator for Bdpt {
+    /// Without a camera to splat onto, light subpaths only ever join camera
+    /// subpaths past their first vertex.
+    fn li(&self, world: &World, ray: &Ray, max_depth: usize) -> LobeColors {
+        self.trace(world, None, ray, max_depth, &mut vec![])
+    }
+
+    fn li_splat(
+        &self,
+        world: &World,
+        camera: &dyn Camera,
+        ray: &Ray,
+        max_depth: usize,
+        splats: &mut Vec<Splat>,
+    ) -> LobeColors {
+        self.trace(world, Some(camera), ray, max_depth, splats)
+    }
+}
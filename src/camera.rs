@@ -1,24 +1,127 @@
+use std::{f32::consts::PI, sync::Arc};
+
 use bvh::ray::Ray;
 use glam::Vec3;
 
-use crate::rand_in_disk;
+use crate::{
+    distribution::Distribution2D, rand_in_disk, random, texture::Texture, world::World,
+};
+
+/// Shape of the lens opening, which is also the shape out-of-focus
+/// highlights take on.
+#[derive(Clone)]
+pub enum Aperture {
+    Disk,
+    Polygon { blades: usize, rotation: f32 },
+    /// Opening shaped like a mask over the unit square, built by
+    /// `Aperture::texture`
+    Texture(Arc<Distribution2D>),
+}
+
+/// Cells the mask of a textured aperture is sampled in along each side.
+const MASK_RESOLUTION: usize = 64;
+
+impl Aperture {
+    /// Aperture letting light through where `mask` is bright, its brightest
+    /// channel weighing each point.
+    pub fn texture(mask: Arc<dyn Texture>) -> Self {
+        let n = MASK_RESOLUTION;
+        let weights: Vec<f32> = (0..n * n)
+            .map(|i| {
+                let u = ((i % n) as f32 + 0.5) / n as f32;
+                let v = ((i / n) as f32 + 0.5) / n as f32;
+                let p = Vec3::new(2. * u - 1., 2. * v - 1., 0.);
+                mask.value(u, v, &p).max_element().max(0.)
+            })
+            .collect();
+        Aperture::Texture(Arc::new(Distribution2D::new(&weights, n, n)))
+    }
+
+    /// Returns a point on the unit aperture in the xy plane.
+    pub fn sample(&self) -> Vec3 {
+        match self {
+            Aperture::Disk => rand_in_disk(),
+            Aperture::Polygon { blades, rotation } => {
+                let blades = (*blades).max(3);
+                let step = 2. * PI / blades as f32;
+                let blade = ((random() * blades as f32) as usize).min(blades - 1);
+                let theta = rotation + blade as f32 * step;
+                let a = Vec3::new(theta.cos(), theta.sin(), 0.);
+                let b = Vec3::new((theta + step).cos(), (theta + step).sin(), 0.);
+
+                // Uniform point in the triangle (center, a, b)
+                let mut s = random();
+                let mut t = random();
+                if s + t > 1. {
+                    s = 1. - s;
+                    t = 1. - t;
+                }
+                s * a + t * b
+            }
+            Aperture::Texture(distribution) => {
+                let (uv, _) = distribution.sample(random(), random());
+                Vec3::new(2. * uv.x - 1., 2. * uv.y - 1., 0.)
+            }
+        }
+    }
+}
+
+/// How a camera sees a point in the scene, see `Camera::importance`.
+pub struct Importance {
+    /// Image point the point shows up at, like `get_ray` takes
+    pub u: f32,
+    pub v: f32,
+    /// Where the ray towards the point leaves the camera
+    pub origin: Vec3,
+    /// Importance of the ray, normalized so it integrates to one over the
+    /// image at a distance of one
+    pub we: f32,
+    /// Solid angle pdf of `get_ray` picking the ray for a uniform `(u, v)`
+    pub pdf: f32,
+    /// Cosine between the ray and the view direction
+    pub cos: f32,
+}
+
+pub trait Camera: Sync + Send {
+    /// Returns the ray through the image point `(u, v)`, both in `[0, 1]`
+    /// with `(0, 0)` at the bottom left.
+    fn get_ray(&self, u: f32, v: f32) -> Ray;
 
-pub struct Camera {
+    fn aspect_ratio(&self) -> f32;
+
+    /// Projects `p` onto the image, for integrators that trace from the
+    /// lights. Only cameras with a single point of view can do this, the
+    /// rest return `None`, as does any `p` that projects outside the image.
+    fn importance(&self, _p: Vec3) -> Option<Importance> {
+        None
+    }
+}
+
+fn basis(origin: Vec3, lookat: Vec3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (origin - lookat).normalize();
+    let u = vup.cross(w).normalize();
+    let v = w.cross(u);
+    (u, v, w)
+}
+
+pub struct PerspectiveCamera {
     pub aspect_ratio: f32,
     pub viewport_height: f32,
     pub viewport_width: f32,
     pub focal_length: f32,
+    pub focus_dist: f32,
     pub origin: Vec3,
     pub horizontal: Vec3,
     pub vertical: Vec3,
     pub lower_left_corner: Vec3,
     pub lens_radius: f32,
+    pub aperture: Aperture,
     pub u: Vec3,
     pub v: Vec3,
     pub w: Vec3,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         origin: Vec3,
         lookat: Vec3,
@@ -34,33 +137,55 @@ impl Camera {
         let viewport_width = aspect_ratio * viewport_height;
         let focal_length = 1.;
 
-        let w = (origin - lookat).normalize();
-        let u = vup.cross(w).normalize();
-        let v = w.cross(u);
-
-        let horizontal = focus_dist * viewport_width * u;
-        let vertical = focus_dist * viewport_height * v;
+        let (u, v, w) = basis(origin, lookat, vup);
 
-        let lower_left_corner = origin - horizontal / 2. - vertical / 2. - focus_dist * w;
         let lens_radius = aperture / 2.;
-        Self {
+        let mut camera = Self {
             aspect_ratio,
             viewport_height,
             viewport_width,
             focal_length,
-            horizontal,
-            vertical,
-            lower_left_corner,
+            focus_dist,
+            horizontal: Vec3::ZERO,
+            vertical: Vec3::ZERO,
+            lower_left_corner: Vec3::ZERO,
             origin,
             lens_radius,
+            aperture: Aperture::Disk,
             u,
             v,
             w,
+        };
+        camera.set_focus_dist(focus_dist);
+        camera
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn set_focus_dist(&mut self, focus_dist: f32) {
+        self.focus_dist = focus_dist;
+        self.horizontal = focus_dist * self.viewport_width * self.u;
+        self.vertical = focus_dist * self.viewport_height * self.v;
+        self.lower_left_corner =
+            self.origin - self.horizontal / 2. - self.vertical / 2. - focus_dist * self.w;
+    }
+
+    /// Focuses on whatever the ray through the center of the image hits first.
+    /// The focus distance is left alone if that ray escapes the scene.
+    pub fn autofocus(&mut self, world: &World) {
+        let ray = Ray::new(self.origin, -self.w);
+        if let Some((_, intersection)) = world.first_intersection(ray, 0.00001, f32::INFINITY) {
+            self.set_focus_dist(intersection.distance);
         }
     }
+}
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        let rd = self.lens_radius * rand_in_disk();
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Ray {
+        let rd = self.lens_radius * self.aperture.sample();
         let offset = self.u * rd.x + self.v * rd.y;
 
         Ray::new(
@@ -68,4 +193,143 @@ impl Camera {
             self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset,
         )
     }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
+    fn importance(&self, p: Vec3) -> Option<Importance> {
+        if self.lens_radius > 0. {
+            return None;
+        }
+        let dir = (p - self.origin).normalize();
+        let cos = dir.dot(-self.w);
+        if cos <= 0. {
+            return None;
+        }
+        let on_plane = self.origin + dir * (self.focus_dist / cos) - self.lower_left_corner;
+        let u = on_plane.dot(self.horizontal) / self.horizontal.length_squared();
+        let v = on_plane.dot(self.vertical) / self.vertical.length_squared();
+        if !(0. ..1.).contains(&u) || !(0. ..1.).contains(&v) {
+            return None;
+        }
+        let area = self.viewport_width * self.viewport_height;
+        Some(Importance {
+            u,
+            v,
+            origin: self.origin,
+            we: 1. / (area * cos.powi(4)),
+            pdf: 1. / (area * cos.powi(3)),
+            cos,
+        })
+    }
+}
+
+/// Parallel projection, `height` is the size of the view in world units.
+pub struct OrthographicCamera {
+    pub aspect_ratio: f32,
+    pub horizontal: Vec3,
+    pub vertical: Vec3,
+    pub lower_left_corner: Vec3,
+    pub w: Vec3,
+}
+
+impl OrthographicCamera {
+    pub fn new(origin: Vec3, lookat: Vec3, vup: Vec3, height: f32, aspect_ratio: f32) -> Self {
+        let (u, v, w) = basis(origin, lookat, vup);
+        let horizontal = height * aspect_ratio * u;
+        let vertical = height * v;
+        let lower_left_corner = origin - horizontal / 2. - vertical / 2.;
+        Self {
+            aspect_ratio,
+            horizontal,
+            vertical,
+            lower_left_corner,
+            w,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Ray {
+        Ray::new(
+            self.lower_left_corner + u * self.horizontal + v * self.vertical,
+            -self.w,
+        )
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+}
+
+/// Equidistant fisheye, the angle from the view axis grows linearly with the
+/// distance from the image center. `fov` is the angle covered by the image
+/// circle inscribed in the frame, the corners see beyond it.
+pub struct FisheyeCamera {
+    pub aspect_ratio: f32,
+    pub half_fov: f32,
+    pub origin: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl FisheyeCamera {
+    pub fn new(origin: Vec3, lookat: Vec3, vup: Vec3, fov: f32, aspect_ratio: f32) -> Self {
+        let (u, v, w) = basis(origin, lookat, vup);
+        Self {
+            aspect_ratio,
+            half_fov: fov.to_radians() / 2.,
+            origin,
+            u,
+            v,
+            w,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Ray {
+        let x = (2. * u - 1.) * self.aspect_ratio;
+        let y = 2. * v - 1.;
+        let r = (x * x + y * y).sqrt();
+        let theta = r * self.half_fov;
+        let phi = y.atan2(x);
+        let dir = theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+        Ray::new(self.origin, dir)
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+}
+
+/// Full 360x180 degree latitude/longitude panorama centered on `lookat`,
+/// meant to be rendered at a 2:1 aspect ratio.
+pub struct EquirectangularCamera {
+    pub origin: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl EquirectangularCamera {
+    pub fn new(origin: Vec3, lookat: Vec3, vup: Vec3) -> Self {
+        let (u, v, w) = basis(origin, lookat, vup);
+        Self { origin, u, v, w }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Ray {
+        let phi = (u - 0.5) * 2. * PI;
+        let theta = (v - 0.5) * PI;
+        let dir = theta.cos() * (phi.sin() * self.u - phi.cos() * self.w) + theta.sin() * self.v;
+        Ray::new(self.origin, dir)
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        2.
+    }
 }
@@ -10,6 +10,10 @@ pub trait RGB {
     fn set_g(&mut self, g: f32);
     fn set_b(&mut self, b: f32);
 
+    fn luminance(&self) -> f32 {
+        0.2126 * self.r() + 0.7152 * self.g() + 0.0722 * self.b()
+    }
+
     fn to_px(&self, samples: usize) -> Rgb<u8> {
         let scale = 1. / samples as f32;
         let r: u8 = ((self.r() * scale).sqrt() * 255.9999) as u8;
@@ -44,3 +48,12 @@ impl RGB for Color {
         self.z = b
     }
 }
+
+/// Converts CIE XYZ to linear sRGB primaries.
+pub fn xyz_to_rgb(xyz: glam::Vec3) -> Color {
+    Color::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
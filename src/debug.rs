@@ -0,0 +1,92 @@
+use bvh::ray::Ray;
+use glam::Vec3;
+
+use crate::{
+    color::Color,
+    integrator::Integrator,
+    lpe::{Lobe, LobeColors},
+    stats,
+    world::World,
+};
+
+/// Views of the scene's geometry and acceleration structure that replace
+/// shading altogether, so nothing in the scene has to change to use them.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DebugMode {
+    /// Interpolated normal used for shading, mapped from [-1, 1]
+    ShadingNormal,
+    /// Normal of the actual surface, faceted on meshes
+    GeometricNormal,
+    Uv,
+    /// Barycentric coordinates of triangle hits as red, green and blue
+    Barycentrics,
+    /// BVH nodes the camera ray visited plus the objects and triangles it
+    /// had to intersect, what finding its hit cost, black for none up to
+    /// red at `scale`
+    IntersectionTests { scale: f32 },
+    /// Triangles the camera ray had to test, black for none up to red at
+    /// `scale`
+    TriangleCount { scale: f32 },
+}
+
+impl Integrator for DebugMode {
+    fn li(&self, world: &World, ray: &Ray, _max_depth: usize) -> LobeColors {
+        let mode = *self;
+        let color = match mode {
+            DebugMode::IntersectionTests { scale } => {
+                // Read off the counters the statistics are gathered with
+                let before = stats::peek();
+                world.first_intersection(*ray, 0.00001, f32::INFINITY);
+                let after = stats::peek();
+                let nodes = after.bvh_nodes_visited - before.bvh_nodes_visited;
+                let objects = after.objects_tested - before.objects_tested;
+                let cost = nodes + objects + triangles_tested(world, ray) as u64;
+                heat(cost as f32 / scale)
+            }
+            DebugMode::TriangleCount { scale } => heat(triangles_tested(world, ray) as f32 / scale),
+            _ => match world.first_intersection(*ray, 0.00001, f32::INFINITY) {
+                Some((obj, intersection)) => match mode {
+                    DebugMode::ShadingNormal => (intersection.norm + 1.) * 0.5,
+                    DebugMode::GeometricNormal => {
+                        (obj.surface.geometric_normal(ray, &intersection) + 1.) * 0.5
+                    }
+                    DebugMode::Uv => Vec3::new(intersection.u, intersection.v, 0.),
+                    _ => Vec3::new(
+                        1. - intersection.u - intersection.v,
+                        intersection.u,
+                        intersection.v,
+                    ),
+                },
+                None => Color::ZERO,
+            },
+        };
+        // Squared so the output's gamma brings back the exact values
+        let color = color.clamp(Vec3::ZERO, Vec3::ONE);
+        let mut lobes = LobeColors::default();
+        lobes.add(Lobe::Emission, color * color);
+        lobes
+    }
+}
+
+/// Triangles tested inside the objects whose bounds `ray` passes through.
+fn triangles_tested(world: &World, ray: &Ray) -> usize {
+    world
+        .candidates(ray)
+        .map(|obj| obj.surface.triangles_tested(ray))
+        .sum()
+}
+
+/// Black through blue, green and yellow to red as `t` goes from 0 to 1.
+fn heat(t: f32) -> Color {
+    let t = t.clamp(0., 1.);
+    let stops = [
+        Vec3::ZERO,
+        Vec3::new(0., 0., 1.),
+        Vec3::new(0., 1., 0.),
+        Vec3::new(1., 1., 0.),
+        Vec3::new(1., 0., 0.),
+    ];
+    let x = t * (stops.len() - 1) as f32;
+    let i = (x as usize).min(stops.len() - 2);
+    stops[i].lerp(stops[i + 1], x - i as f32)
+}
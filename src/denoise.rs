@@ -0,0 +1,156 @@
+use glam::Vec3;
+use rayon::prelude::*;
+
+use crate::{
+    aov::AovBuffers,
+    color::{Color, RGB},
+};
+
+/// Edge-avoiding à-trous wavelet filter after Schied et al., "Spatiotemporal
+/// Variance-Guided Filtering", minus the temporal part.
+///
+/// The color is divided by the albedo first so texture detail isn't blurred,
+/// only the lighting is. Each iteration is a 5x5 B3 spline with its taps
+/// spread twice as far apart as the last, and taps are only trusted as far as
+/// their normal, depth and luminance agree with the center pixel. The
+/// luminance test is scaled by the pixel's variance, so noisy pixels are
+/// smoothed harder than converged ones.
+#[derive(Clone, Copy)]
+pub struct Denoiser {
+    pub iterations: usize,
+    /// How sharply normals have to agree, as a power of their dot product
+    pub sigma_normal: f32,
+    /// Allowed depth difference, relative to the depth
+    pub sigma_depth: f32,
+    /// Allowed luminance difference, in standard deviations
+    pub sigma_luminance: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_normal: 128.,
+            sigma_depth: 0.05,
+            sigma_luminance: 4.,
+        }
+    }
+}
+
+const KERNEL: [f32; 3] = [3. / 8., 1. / 4., 1. / 16.];
+
+impl Denoiser {
+    /// `variance` is the variance of each pixel's mean luminance.
+    pub fn denoise(&self, color: &[Color], variance: &[f32], aovs: &AovBuffers) -> Vec<Color> {
+        let width = aovs.width;
+        let height = aovs.height;
+
+        let albedo: Vec<Color> = aovs.albedo.iter().map(|a| a.max(Vec3::splat(0.01))).collect();
+        let mut irradiance: Vec<Color> = color.iter().zip(&albedo).map(|(c, a)| *c / *a).collect();
+        let mut variance: Vec<f32> = variance
+            .iter()
+            .zip(&albedo)
+            .map(|(v, a)| v / (a.luminance() * a.luminance()))
+            .collect();
+
+        for iteration in 0..self.iterations {
+            let step = 1isize << iteration;
+            let (next, next_variance): (Vec<Color>, Vec<f32>) = (0..width * height)
+                .into_par_iter()
+                .map(|i| self.filter_pixel(i, step, width, height, &irradiance, &variance, aovs))
+                .unzip();
+            irradiance = next;
+            variance = next_variance;
+        }
+
+        irradiance.iter().zip(&albedo).map(|(c, a)| *c * *a).collect()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn filter_pixel(
+        &self,
+        i: usize,
+        step: isize,
+        width: usize,
+        height: usize,
+        irradiance: &[Color],
+        variance: &[f32],
+        aovs: &AovBuffers,
+    ) -> (Color, f32) {
+        let x = (i % width) as isize;
+        let y = (i / width) as isize;
+        let normal = aovs.normal[i];
+        let depth = aovs.depth[i];
+        let luminance = irradiance[i].luminance();
+        // Slightly blurred variance is much more reliable than a single pixel's
+        let sigma = self.sigma_luminance * self.blurred_variance(x, y, width, height, variance).sqrt()
+            + 1e-4;
+
+        let mut sum = Color::ZERO;
+        let mut sum_variance = 0.;
+        let mut total = 0.;
+        for dy in -2isize..=2 {
+            for dx in -2isize..=2 {
+                let qx = x + dx * step;
+                let qy = y + dy * step;
+                if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                    continue;
+                }
+                let j = qx as usize + qy as usize * width;
+                let kernel = KERNEL[dx.unsigned_abs()] * KERNEL[dy.unsigned_abs()];
+
+                let w_normal = normal.dot(aovs.normal[j]).max(0.).powf(self.sigma_normal);
+                let w_depth = if depth.is_finite() && aovs.depth[j].is_finite() {
+                    (-(depth - aovs.depth[j]).abs() / (self.sigma_depth * depth + 1e-4)).exp()
+                } else if depth.is_finite() == aovs.depth[j].is_finite() {
+                    1.
+                } else {
+                    0.
+                };
+                // Background pixels have no normal, only depth tells them apart
+                let w_normal = if normal == Vec3::ZERO && aovs.normal[j] == Vec3::ZERO {
+                    1.
+                } else {
+                    w_normal
+                };
+                let w_luminance = (-(luminance - irradiance[j].luminance()).abs() / sigma).exp();
+
+                let w = kernel * w_normal * w_depth * w_luminance;
+                sum += irradiance[j] * w;
+                sum_variance += w * w * variance[j];
+                total += w;
+            }
+        }
+
+        if total <= 0. {
+            return (irradiance[i], variance[i]);
+        }
+        (sum / total, sum_variance / (total * total))
+    }
+
+    fn blurred_variance(
+        &self,
+        x: isize,
+        y: isize,
+        width: usize,
+        height: usize,
+        variance: &[f32],
+    ) -> f32 {
+        let gaussian = [0.25, 0.5, 0.25];
+        let mut sum = 0.;
+        let mut total = 0.;
+        for dy in -1isize..=1 {
+            for dx in -1isize..=1 {
+                let qx = x + dx;
+                let qy = y + dy;
+                if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                    continue;
+                }
+                let w = gaussian[(dx + 1) as usize] * gaussian[(dy + 1) as usize];
+                sum += w * variance[qx as usize + qy as usize * width];
+                total += w;
+            }
+        }
+        sum / total
+    }
+}
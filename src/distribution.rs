@@ -0,0 +1,231 @@
+use glam::Vec2;
+
+/// Piecewise-constant distribution over `[0, 1)` built from unnormalized
+/// function values.
+pub struct Distribution1D {
+    pub func: Vec<f32>,
+    pub cdf: Vec<f32>,
+    pub integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1] / n as f32;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            if integral == 0. {
+                *c = i as f32 / n as f32;
+            } else {
+                *c /= integral;
+            }
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Returns the sampled position, its pdf and the index of the segment it
+    /// fell in.
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.count();
+        let offset = self
+            .cdf
+            .partition_point(|c| *c <= u)
+            .saturating_sub(1)
+            .min(n - 1);
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0. {
+            du /= width;
+        }
+        let pdf = if self.integral > 0. {
+            self.func[offset] / self.integral
+        } else {
+            1.
+        };
+        ((offset as f32 + du) / n as f32, pdf, offset)
+    }
+
+    pub fn pdf(&self, x: f32) -> f32 {
+        if self.integral == 0. {
+            return 1.;
+        }
+        let idx = ((x * self.count() as f32) as usize).min(self.count() - 1);
+        self.func[idx] / self.integral
+    }
+}
+
+/// Piecewise-constant distribution over `[0, 1)^2`, `func` is laid out in
+/// rows of `nu` values.
+pub struct Distribution2D {
+    pub conditional: Vec<Distribution1D>,
+    pub marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], nu: usize, nv: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..nv)
+            .map(|v| Distribution1D::new(func[v * nu..(v + 1) * nu].to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    pub fn sample(&self, u0: f32, u1: f32) -> (Vec2, f32) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u0);
+        (Vec2::new(u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, p: Vec2) -> f32 {
+        if self.marginal.integral == 0. {
+            return 1.;
+        }
+        let nu = self.conditional[0].count();
+        let nv = self.marginal.count();
+        let iu = ((p.x * nu as f32) as usize).min(nu - 1);
+        let iv = ((p.y * nv as f32) as usize).min(nv - 1);
+        self.conditional[iv].func[iu] / self.marginal.integral
+    }
+}
+
+/// Walker/Vose alias table, samples an index proportionally to its weight in
+/// constant time.
+pub struct AliasTable {
+    prob: Vec<f32>,
+    alias: Vec<usize>,
+    pmf: Vec<f32>,
+}
+
+impl AliasTable {
+    pub fn new(weights: &[f32]) -> Self {
+        let n = weights.len();
+        let total: f32 = weights.iter().sum();
+        let pmf: Vec<f32> = weights.iter().map(|w| w / total).collect();
+        let mut scaled: Vec<f32> = pmf.iter().map(|p| p * n as f32).collect();
+        let mut prob = vec![1.; n];
+        let mut alias: Vec<usize> = (0..n).collect();
+
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).partition(|&i| scaled[i] < 1.);
+        while !small.is_empty() && !large.is_empty() {
+            let s = small.pop().unwrap();
+            let l = large.pop().unwrap();
+            prob[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] += scaled[s] - 1.;
+            if scaled[l] < 1. {
+                small.push(l);
+            } else {
+                large.push(l);
+            }
+        }
+
+        Self { prob, alias, pmf }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pmf.len()
+    }
+
+    /// Returns the sampled index and its probability.
+    pub fn sample(&self, u: f32) -> (usize, f32) {
+        let x = u * self.len() as f32;
+        let i = (x as usize).min(self.len() - 1);
+        let idx = if x - (i as f32) < self.prob[i] {
+            i
+        } else {
+            self.alias[i]
+        };
+        (idx, self.pmf[idx])
+    }
+
+    pub fn pmf(&self, idx: usize) -> f32 {
+        self.pmf[idx]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How often each index comes out for `n` evenly spaced `u`.
+    fn frequencies(len: usize, n: usize, sample: impl Fn(f32) -> usize) -> Vec<f32> {
+        let mut counts = vec![0; len];
+        for k in 0..n {
+            counts[sample((k as f32 + 0.5) / n as f32)] += 1;
+        }
+        counts.iter().map(|&c| c as f32 / n as f32).collect()
+    }
+
+    #[test]
+    fn alias_table_matches_weights() {
+        let weights = [1., 0., 3., 0.5, 5.5];
+        let total: f32 = weights.iter().sum();
+        let table = AliasTable::new(&weights);
+        let freq = frequencies(weights.len(), 100_000, |u| table.sample(u).0);
+        for (i, w) in weights.iter().enumerate() {
+            assert!(
+                (freq[i] - w / total).abs() < 1e-3,
+                "index {}: {}",
+                i,
+                freq[i]
+            );
+            assert!((table.pmf(i) - w / total).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn distribution_1d_matches_func() {
+        let func = vec![2., 0., 1., 5.];
+        let total: f32 = func.iter().sum();
+        let dist = Distribution1D::new(func.clone());
+        let freq = frequencies(func.len(), 100_000, |u| dist.sample_continuous(u).2);
+        for (i, f) in func.iter().enumerate() {
+            assert!(
+                (freq[i] - f / total).abs() < 1e-3,
+                "segment {}: {}",
+                i,
+                freq[i]
+            );
+        }
+
+        let (x, pdf, i) = dist.sample_continuous(0.9);
+        assert_eq!(i, 3);
+        assert!((pdf - dist.pdf(x)).abs() < 1e-6);
+        // Piecewise constant over [0, 1), so the pdf is the segment's share
+        // times the number of segments
+        assert!((pdf - 5. / total * func.len() as f32).abs() < 1e-5);
+    }
+
+    #[test]
+    fn distribution_2d_pdf_integrates_to_one() {
+        let func = [1., 2., 0., 4., 0.5, 0.5];
+        let dist = Distribution2D::new(&func, 3, 2);
+        let n = 64;
+        let mut integral = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                let p = Vec2::new((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                integral += dist.pdf(p) / (n * n) as f32;
+            }
+        }
+        assert!((integral - 1.).abs() < 1e-4);
+
+        let (p, pdf) = dist.sample(0.3, 0.7);
+        assert!((pdf - dist.pdf(p)).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,218 @@
+use std::f32::consts::PI;
+
+use crate::color::Color;
+
+/// Pixel reconstruction filter, weights a sample by its offset from a pixel
+/// center in pixels.
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Box { radius: f32 },
+    Gaussian { radius: f32, alpha: f32 },
+    MitchellNetravali { radius: f32, b: f32, c: f32 },
+    BlackmanHarris { radius: f32 },
+}
+
+impl Filter {
+    pub fn gaussian() -> Self {
+        Filter::Gaussian {
+            radius: 1.5,
+            alpha: 2.,
+        }
+    }
+
+    pub fn mitchell() -> Self {
+        Filter::MitchellNetravali {
+            radius: 2.,
+            b: 1. / 3.,
+            c: 1. / 3.,
+        }
+    }
+
+    pub fn blackman_harris() -> Self {
+        Filter::BlackmanHarris { radius: 2. }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::MitchellNetravali { radius, .. }
+            | Filter::BlackmanHarris { radius } => radius,
+        }
+    }
+
+    pub fn eval(&self, dx: f32, dy: f32) -> f32 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+
+    fn eval_1d(&self, d: f32) -> f32 {
+        let d = d.abs();
+        match *self {
+            Filter::Box { radius } => {
+                if d <= radius {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Filter::Gaussian { radius, alpha } => {
+                ((-alpha * d * d).exp() - (-alpha * radius * radius).exp()).max(0.)
+            }
+            Filter::MitchellNetravali { radius, b, c } => {
+                let x = 2. * d / radius;
+                if x > 2. {
+                    0.
+                } else if x > 1. {
+                    ((-b - 6. * c) * x.powi(3)
+                        + (6. * b + 30. * c) * x * x
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c))
+                        / 6.
+                } else {
+                    ((12. - 9. * b - 6. * c) * x.powi(3)
+                        + (-18. + 12. * b + 6. * c) * x * x
+                        + (6. - 2. * b))
+                        / 6.
+                }
+            }
+            Filter::BlackmanHarris { radius } => {
+                if d > radius {
+                    return 0.;
+                }
+                let n = (d / radius + 1.) / 2.;
+                0.35875 - 0.48829 * (2. * PI * n).cos() + 0.14128 * (4. * PI * n).cos()
+                    - 0.01168 * (6. * PI * n).cos()
+            }
+        }
+    }
+}
+
+/// Weighted sum of the samples splatted onto a rectangle of pixels. `x0` and
+/// `y0` place it in the image, rows go top to bottom.
+///
+/// Light that integrators carry straight from the lights to the camera goes
+/// to `splat` instead. It isn't tied to any camera sample, so it is left
+/// unfiltered and scaled by the number of camera samples per pixel.
+#[derive(Clone)]
+pub struct Film {
+    pub x0: isize,
+    pub y0: isize,
+    pub width: usize,
+    pub height: usize,
+    pub sum: Vec<Color>,
+    pub weight: Vec<f32>,
+    pub splat: Vec<Color>,
+    /// Camera samples taken for the whole film
+    pub samples: usize,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::tile(0, 0, width, height)
+    }
+
+    pub fn tile(x0: isize, y0: isize, width: usize, height: usize) -> Self {
+        Self {
+            x0,
+            y0,
+            width,
+            height,
+            sum: vec![Color::ZERO; width * height],
+            weight: vec![0.; width * height],
+            splat: vec![Color::ZERO; width * height],
+            samples: 0,
+        }
+    }
+
+    /// Tile big enough to hold every splat from samples landing inside the
+    /// given pixels.
+    pub fn padded_tile(filter: &Filter, x0: usize, y0: usize, width: usize, height: usize) -> Self {
+        let pad = filter.radius().ceil() as usize;
+        Self::tile(
+            x0 as isize - pad as isize,
+            y0 as isize - pad as isize,
+            width + 2 * pad,
+            height + 2 * pad,
+        )
+    }
+
+    /// Adds a sample at image position `(x, y)`, measured in pixels from the
+    /// top left corner, to every pixel the filter reaches.
+    pub fn add_sample(&mut self, filter: &Filter, x: f32, y: f32, color: Color) {
+        let r = filter.radius();
+        let min_x = ((x - 0.5 - r).ceil() as isize).max(self.x0);
+        let max_x = ((x - 0.5 + r).floor() as isize).min(self.x0 + self.width as isize - 1);
+        let min_y = ((y - 0.5 - r).ceil() as isize).max(self.y0);
+        let max_y = ((y - 0.5 + r).floor() as isize).min(self.y0 + self.height as isize - 1);
+        for py in min_y..=max_y {
+            for px in min_x..=max_x {
+                let w = filter.eval(px as f32 + 0.5 - x, py as f32 + 0.5 - y);
+                if w == 0. {
+                    continue;
+                }
+                let i = (px - self.x0) as usize + (py - self.y0) as usize * self.width;
+                self.sum[i] += color * w;
+                self.weight[i] += w;
+            }
+        }
+    }
+
+    /// Adds light to the pixel under image position `(x, y)`, dropped if
+    /// it's off the film.
+    pub fn add_splat(&mut self, x: f32, y: f32, color: Color) {
+        let px = x.floor() as isize - self.x0;
+        let py = y.floor() as isize - self.y0;
+        if px < 0 || py < 0 || px >= self.width as isize || py >= self.height as isize {
+            return;
+        }
+        self.splat[px as usize + py as usize * self.width] += color;
+    }
+
+    /// Adds `tile` onto the pixels it overlaps.
+    pub fn merge(&mut self, tile: &Film) {
+        self.samples += tile.samples;
+        for ty in 0..tile.height {
+            let y = tile.y0 + ty as isize - self.y0;
+            if y < 0 || y >= self.height as isize {
+                continue;
+            }
+            for tx in 0..tile.width {
+                let x = tile.x0 + tx as isize - self.x0;
+                if x < 0 || x >= self.width as isize {
+                    continue;
+                }
+                let i = x as usize + y as usize * self.width;
+                let j = tx + ty * tile.width;
+                self.sum[i] += tile.sum[j];
+                self.weight[i] += tile.weight[j];
+                self.splat[i] += tile.splat[j];
+            }
+        }
+    }
+
+    /// Filtered color of pixel `i`, negative lobes can leave it below zero
+    /// so it is clamped.
+    pub fn color(&self, i: usize) -> Color {
+        self.splat_color(i) + self.filtered(i).max(Color::ZERO)
+    }
+
+    /// Like `color` without the clamp, so films holding parts of an image
+    /// add up to the whole.
+    pub fn color_unclamped(&self, i: usize) -> Color {
+        self.splat_color(i) + self.filtered(i)
+    }
+
+    fn filtered(&self, i: usize) -> Color {
+        if self.weight[i] == 0. {
+            return Color::ZERO;
+        }
+        self.sum[i] / self.weight[i]
+    }
+
+    fn splat_color(&self, i: usize) -> Color {
+        if self.samples == 0 {
+            return Color::ZERO;
+        }
+        self.splat[i] * (self.width * self.height) as f32 / self.samples as f32
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs;
+
+/// Candela distribution read from an IESNA LM-63 photometric file, scaled so
+/// the brightest direction is 1.
+pub struct IesProfile {
+    /// Degrees away from the light's axis
+    pub vertical: Vec<f32>,
+    /// Degrees around the light's axis
+    pub horizontal: Vec<f32>,
+    /// One row of `vertical.len()` values per horizontal angle
+    pub candela: Vec<f32>,
+}
+
+impl IesProfile {
+    pub fn from_file(path: &str) -> Self {
+        let text = fs::read_to_string(path).expect("IES file to be readable");
+        Self::parse(&text).expect("IES file to be valid")
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        loop {
+            let line = lines.next()?.trim();
+            if let Some(tilt) = line.strip_prefix("TILT=") {
+                // Embedded lamp tilt tables are not supported
+                if tilt.trim() == "INCLUDE" {
+                    return None;
+                }
+                break;
+            }
+        }
+
+        let values: Vec<f32> = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f32>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+
+        let multiplier = *values.get(2)?;
+        let n_vertical = *values.get(3)? as usize;
+        let n_horizontal = *values.get(4)? as usize;
+        // 10 photometric values followed by ballast factor, future use and
+        // input watts
+        let start = 13;
+        let vertical = values.get(start..start + n_vertical)?.to_vec();
+        let start = start + n_vertical;
+        let horizontal = values.get(start..start + n_horizontal)?.to_vec();
+        let start = start + n_horizontal;
+        let mut candela: Vec<f32> = values
+            .get(start..start + n_vertical * n_horizontal)?
+            .iter()
+            .map(|c| c * multiplier)
+            .collect();
+
+        let max = candela.iter().cloned().fold(0., f32::max);
+        if max > 0. {
+            candela.iter_mut().for_each(|c| *c /= max);
+        }
+
+        Some(Self {
+            vertical,
+            horizontal,
+            candela,
+        })
+    }
+
+    /// Relative intensity `theta` degrees off the axis and `phi` degrees
+    /// around it.
+    pub fn value(&self, theta: f32, phi: f32) -> f32 {
+        let last_h = *self.horizontal.last().unwrap_or(&0.);
+        let mut phi = phi.rem_euclid(360.);
+        // Files only store the part of the distribution that isn't mirrored
+        if last_h <= 90. {
+            phi = phi % 180.;
+            if phi > 90. {
+                phi = 180. - phi;
+            }
+        } else if last_h <= 180. && phi > 180. {
+            phi = 360. - phi;
+        }
+
+        let (h0, h1, th) = lerp_index(&self.horizontal, phi);
+        let (v0, v1, tv) = match self.vertical_index(theta) {
+            Some(index) => index,
+            None => return 0.,
+        };
+        let n = self.vertical.len();
+        let at = |h: usize, v: usize| self.candela[h * n + v];
+        let a = at(h0, v0) * (1. - tv) + at(h0, v1) * tv;
+        let b = at(h1, v0) * (1. - tv) + at(h1, v1) * tv;
+        a * (1. - th) + b * th
+    }
+
+    fn vertical_index(&self, theta: f32) -> Option<(usize, usize, f32)> {
+        let first = *self.vertical.first()?;
+        let last = *self.vertical.last()?;
+        if theta < first || theta > last {
+            return None;
+        }
+        Some(lerp_index(&self.vertical, theta))
+    }
+}
+
+/// Finds the pair of entries in the sorted `angles` around `x` and how far
+/// between them it lies, clamping at the ends.
+fn lerp_index(angles: &[f32], x: f32) -> (usize, usize, f32) {
+    if angles.len() < 2 {
+        return (0, 0, 0.);
+    }
+    let i = angles
+        .partition_point(|a| *a <= x)
+        .clamp(1, angles.len() - 1);
+    let (a, b) = (angles[i - 1], angles[i]);
+    let t = if b > a {
+        ((x - a) / (b - a)).clamp(0., 1.)
+    } else {
+        0.
+    };
+    (i - 1, i, t)
+}
@@ -6,6 +6,8 @@ use bvh::{
 };
 use glam::{Mat4, Quat, Vec3};
 
+use crate::world::{Surface, SurfaceSample};
+
 pub struct Instance<T> {
     transform: Mat4,
     inv_transform: Mat4,
@@ -37,6 +39,16 @@ impl<T> Instance<T> {
             Mat4::from_scale_rotation_translation(Vec3::ONE, Quat::IDENTITY, translation);
         Self::new(obj, transform)
     }
+
+    /// `ray` in the object's space. Distances along it are scaled compared
+    /// to the world's.
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        let inv = &self.inv_transform;
+        Ray::new(
+            inv.transform_point3(ray.origin),
+            inv.transform_vector3(ray.direction),
+        )
+    }
 }
 
 impl<T> IntersectionRay for Instance<T>
@@ -49,10 +61,8 @@ where
         t_min: bvh::Real,
         t_max: bvh::Real,
     ) -> Option<Intersection> {
-        let inv = &self.inv_transform;
-        let new_dir = inv.transform_vector3(ray.direction);
-        let ray_len = new_dir.length();
-        let local_ray = Ray::new(inv.transform_point3(ray.origin), new_dir);
+        let ray_len = self.inv_transform.transform_vector3(ray.direction).length();
+        let local_ray = self.local_ray(ray);
         //dbg!(ray.origin, local_ray.origin);
         if let Some(intersection) = self.obj.intersects_ray(&local_ray, t_min * ray_len, t_max) {
             let hit_pos = local_ray.at(intersection.distance);
@@ -77,6 +87,59 @@ where
     }
 }
 
+impl<T> Surface for Instance<T>
+where
+    T: IntersectionRay + Surface,
+{
+    fn area(&self, transform: &Mat4) -> f32 {
+        self.obj.area(&(*transform * self.transform))
+    }
+
+    fn sample_surface(&self) -> Option<SurfaceSample> {
+        let sample = self.obj.sample_surface()?;
+        // Normals transform by the inverse transpose, and the length of the
+        // result is how much this spot of the surface got stretched
+        let world_n = self.inv_transform.transpose().transform_vector3(sample.n);
+        let jacobian = self.transform.determinant().abs() * world_n.length();
+        Some(SurfaceSample {
+            p: self.transform.transform_point3(sample.p),
+            n: world_n.normalize(),
+            pdf: sample.pdf / jacobian,
+            ..sample
+        })
+    }
+
+    fn surface_pdf(&self, p: Vec3, n: Vec3) -> f32 {
+        let local_n = self.transform.transpose().transform_vector3(n).normalize();
+        let jacobian = self.transform.determinant().abs()
+            * self
+                .inv_transform
+                .transpose()
+                .transform_vector3(local_n)
+                .length();
+        self.obj
+            .surface_pdf(self.inv_transform.transform_point3(p), local_n)
+            / jacobian
+    }
+
+    fn geometric_normal(&self, ray: &Ray, intersection: &Intersection) -> Vec3 {
+        let local_ray = self.local_ray(ray);
+        let local_inter = match self.obj.intersects_ray(&local_ray, 0., f32::INFINITY) {
+            Some(inter) => inter,
+            None => return intersection.norm,
+        };
+        let n = self.obj.geometric_normal(&local_ray, &local_inter);
+        self.inv_transform
+            .transpose()
+            .transform_vector3(n)
+            .normalize()
+    }
+
+    fn triangles_tested(&self, ray: &Ray) -> usize {
+        self.obj.triangles_tested(&self.local_ray(ray))
+    }
+}
+
 impl<T> Bounded for Instance<T>
 where
     T: Bounded,
@@ -0,0 +1,226 @@
+use bvh::ray::Ray;
+use glam::Vec3;
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    lpe::{Event, Lobe, LobeColors},
+    material::Material,
+    power_heuristic, stats,
+    world::World,
+};
+
+/// Computes the radiance arriving along camera rays, what `World::render`
+/// drives for every sample.
+pub trait Integrator: Sync + Send {
+    /// Radiance along `ray` split by lobe, following paths for at most
+    /// `max_depth` bounces.
+    fn li(&self, world: &World, ray: &Ray, max_depth: usize) -> LobeColors;
+
+    /// Like `li`, for integrators that can also carry light from the lights
+    /// straight to `camera`. That light lands on whichever pixel it hits, so
+    /// it is handed back through `splats`.
+    fn li_splat(
+        &self,
+        world: &World,
+        _camera: &dyn Camera,
+        ray: &Ray,
+        max_depth: usize,
+        _splats: &mut Vec<Splat>,
+    ) -> LobeColors {
+        self.li(world, ray, max_depth)
+    }
+}
+
+/// Light arriving at image point `(u, v)`, in the coordinates `Camera::get_ray`
+/// takes.
+pub struct Splat {
+    pub u: f32,
+    pub v: f32,
+    pub color: Color,
+    /// Lobe film the light also goes to when the image is split by lobe
+    pub lobe: Lobe,
+}
+
+/// What a path did when it scattered off its first surface.
+pub(crate) fn first_event(obj: &dyn Material, norm: Vec3, scattered: &Ray) -> Event {
+    if !obj.is_specular() {
+        Event::Diffuse
+    } else if scattered.direction.dot(norm) < 0. {
+        Event::Transmission
+    } else {
+        Event::Specular
+    }
+}
+
+/// Unidirectional path tracer, sampling lights at every diffuse bounce and
+/// combining that with hitting them by chance through MIS.
+pub struct PathTracer;
+
+impl Integrator for PathTracer {
+    fn li(&self, world: &World, ray: &Ray, max_depth: usize) -> LobeColors {
+        let mut color = LobeColors::default();
+        let mut throughput = Color::ONE;
+        let mut ray = *ray;
+        // Pdf of the direction the current ray was scattered in, None for
+        // camera rays and specular bounces
+        let mut scatter_pdf = None;
+        let mut vertices = 0;
+        let mut first = None;
+
+        for _ in 0..max_depth {
+            if let Some((obj, intersection)) = world.first_intersection(ray, 0.00001, f32::INFINITY)
+            {
+                vertices += 1;
+                let emit = obj.emit(&ray, &intersection);
+                let weight = match scatter_pdf {
+                    Some(pdf) if obj.light_pmf > 0. => {
+                        power_heuristic(pdf, world.emitter_pdf(&ray, &intersection, obj))
+                    }
+                    _ => 1.,
+                };
+                color.add(Lobe::of(first, vertices - 1), throughput * weight * emit);
+
+                if let Some((child_ray, attenuation, pdf)) = obj.scatter(&ray, &intersection) {
+                    if pdf <= 0. {
+                        break;
+                    }
+                    if first.is_none() {
+                        first = Some(first_event(obj, intersection.norm, &child_ray));
+                    }
+                    if obj.is_specular() {
+                        scatter_pdf = None;
+                    } else {
+                        color.add(
+                            Lobe::of(first, vertices),
+                            throughput
+                                * world.direct_light(&ray, &intersection, obj, attenuation, true),
+                        );
+                        scatter_pdf = Some(pdf);
+                    }
+                    throughput *= attenuation * obj.scattering_pdf(&ray, &intersection, &child_ray)
+                        / pdf;
+                    ray = child_ray;
+                } else {
+                    break;
+                }
+            } else {
+                let weight = match scatter_pdf {
+                    Some(pdf) => power_heuristic(pdf, world.background.pdf(ray.direction)),
+                    None => 1.,
+                };
+                color.add(
+                    Lobe::of(first, vertices),
+                    throughput * weight * world.background.value(ray.direction),
+                );
+                break;
+            }
+        }
+
+        stats::count_path(vertices);
+        color
+    }
+}
+
+/// Light reaching the first surface directly, plus whatever that surface
+/// emits. Sees neither reflections nor indirect light, so it is quick and
+/// noise free where lights are small.
+pub struct DirectLighting;
+
+impl Integrator for DirectLighting {
+    fn li(&self, world: &World, ray: &Ray, _max_depth: usize) -> LobeColors {
+        let mut color = LobeColors::default();
+        let (obj, intersection) = match world.first_intersection(*ray, 0.00001, f32::INFINITY) {
+            Some(hit) => hit,
+            None => {
+                color.add(Lobe::Emission, world.background.value(ray.direction));
+                stats::count_path(0);
+                return color;
+            }
+        };
+        color.add(Lobe::Emission, obj.emit(ray, &intersection));
+        stats::count_path(1);
+
+        let (child_ray, attenuation, pdf) = match obj.scatter(ray, &intersection) {
+            Some(scattered) if scattered.2 > 0. && !obj.is_specular() => scattered,
+            _ => return color,
+        };
+        color.add(
+            Lobe::DirectDiffuse,
+            world.direct_light(ray, &intersection, obj, attenuation, true),
+        );
+
+        // The other half of MIS, lights found by scattering
+        let f = attenuation * obj.scattering_pdf(ray, &intersection, &child_ray) / pdf;
+        match world.first_intersection(child_ray, 0.00001, f32::INFINITY) {
+            Some((light, light_hit)) if light.light_pmf > 0. => {
+                let weight = power_heuristic(pdf, world.emitter_pdf(&child_ray, &light_hit, light));
+                color.add(
+                    Lobe::DirectDiffuse,
+                    f * weight * light.emit(&child_ray, &light_hit),
+                );
+            }
+            Some(_) => {}
+            None => {
+                let dir = child_ray.direction;
+                let weight = power_heuristic(pdf, world.background.pdf(dir));
+                color.add(Lobe::DirectDiffuse, f * weight * world.background.value(dir));
+            }
+        }
+        color
+    }
+}
+
+/// Whitted style ray tracing for previews: direct light at diffuse surfaces,
+/// which end the path, and specular surfaces followed for reflections and
+/// refractions.
+pub struct Whitted;
+
+impl Integrator for Whitted {
+    fn li(&self, world: &World, ray: &Ray, max_depth: usize) -> LobeColors {
+        let mut color = LobeColors::default();
+        let mut throughput = Color::ONE;
+        let mut ray = *ray;
+        let mut vertices = 0;
+        let mut first = None;
+
+        for _ in 0..max_depth {
+            let (obj, intersection) = match world.first_intersection(ray, 0.00001, f32::INFINITY) {
+                Some(hit) => hit,
+                None => {
+                    color.add(
+                        Lobe::of(first, vertices),
+                        throughput * world.background.value(ray.direction),
+                    );
+                    break;
+                }
+            };
+            vertices += 1;
+            color.add(
+                Lobe::of(first, vertices - 1),
+                throughput * obj.emit(&ray, &intersection),
+            );
+
+            let (child_ray, attenuation, pdf) = match obj.scatter(&ray, &intersection) {
+                Some(scattered) if scattered.2 > 0. => scattered,
+                _ => break,
+            };
+            if first.is_none() {
+                first = Some(first_event(obj, intersection.norm, &child_ray));
+            }
+            if !obj.is_specular() {
+                color.add(
+                    Lobe::of(first, vertices),
+                    throughput * world.direct_light(&ray, &intersection, obj, attenuation, false),
+                );
+                break;
+            }
+            throughput *=
+                attenuation * obj.scattering_pdf(&ray, &intersection, &child_ray) / pdf;
+            ray = child_ray;
+        }
+
+        stats::count_path(vertices);
+        color
+    }
+}
@@ -0,0 +1,110 @@
+use glam::Vec3;
+
+use crate::color::Color;
+
+/// Lights with no surface, they can't be hit by rays and are only found
+/// through shadow rays.
+pub trait Light: Sync + Send {
+    /// Returns the direction from `p` to the light, the distance to it and
+    /// the radiance arriving at `p`, or `None` if `p` is not lit.
+    fn sample_li(&self, p: Vec3) -> Option<(Vec3, f32, Color)>;
+}
+
+pub struct PointLight {
+    pub position: Vec3,
+    pub intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: Vec3) -> Option<(Vec3, f32, Color)> {
+        let to_light = self.position - p;
+        let dist = to_light.length();
+        Some((to_light / dist, dist, self.intensity / (dist * dist)))
+    }
+}
+
+/// Point light restricted to a cone, full intensity inside `inner_angle` and
+/// falling off smoothly to nothing at `outer_angle`, both in degrees from
+/// `direction`.
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub intensity: Color,
+    pub cos_inner: f32,
+    pub cos_outer: f32,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vec3,
+        lookat: Vec3,
+        intensity: Color,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Self {
+            position,
+            direction: (lookat - position).normalize(),
+            intensity,
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: Vec3) -> Option<(Vec3, f32, Color)> {
+        let to_light = self.position - p;
+        let dist = to_light.length();
+        let dir = to_light / dist;
+        let falloff = cone_falloff((-dir).dot(self.direction), self.cos_inner, self.cos_outer);
+        if falloff <= 0. {
+            return None;
+        }
+        Some((dir, dist, self.intensity * falloff / (dist * dist)))
+    }
+}
+
+/// Light arriving from a single direction everywhere in the scene, like the
+/// sun. `irradiance` is measured on a surface facing the light.
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self {
+            direction: direction.normalize(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _p: Vec3) -> Option<(Vec3, f32, Color)> {
+        Some((-self.direction, f32::INFINITY, self.irradiance))
+    }
+}
+
+/// 1 inside the inner cone, 0 outside the outer one and a smoothstep in
+/// between.
+pub fn cone_falloff(cos_theta: f32, cos_inner: f32, cos_outer: f32) -> f32 {
+    if cos_theta >= cos_inner {
+        return 1.;
+    }
+    if cos_theta <= cos_outer {
+        return 0.;
+    }
+    let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+    t * t * (3. - 2. * t)
+}
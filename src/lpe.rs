@@ -0,0 +1,94 @@
+use std::ops::{AddAssign, Index};
+
+use crate::color::Color;
+
+/// What a path did at the first surface it hit, read off the direction it
+/// scattered in.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    Diffuse,
+    /// Mirror-like reflection
+    Specular,
+    /// Passed through the surface
+    Transmission,
+}
+
+/// Buckets light is sorted into by the events along its path, the way light
+/// path expressions would select them (C camera, D diffuse, S specular,
+/// T transmission, L light):
+///
+/// - `Emission` CL, lights and background seen directly
+/// - `DirectDiffuse` CDL
+/// - `IndirectDiffuse` CD.+L
+/// - `Specular` CS.*L
+/// - `Transmission` CT.*L
+#[derive(Clone, Copy, PartialEq)]
+pub enum Lobe {
+    Emission,
+    DirectDiffuse,
+    IndirectDiffuse,
+    Specular,
+    Transmission,
+}
+
+impl Lobe {
+    pub const ALL: [Lobe; 5] = [
+        Lobe::Emission,
+        Lobe::DirectDiffuse,
+        Lobe::IndirectDiffuse,
+        Lobe::Specular,
+        Lobe::Transmission,
+    ];
+
+    /// Lobe of light that reaches the camera after `bounces` scattering
+    /// events, the first being `first`.
+    pub fn of(first: Option<Event>, bounces: usize) -> Self {
+        match (first, bounces) {
+            (_, 0) | (None, _) => Lobe::Emission,
+            (Some(Event::Diffuse), 1) => Lobe::DirectDiffuse,
+            (Some(Event::Diffuse), _) => Lobe::IndirectDiffuse,
+            (Some(Event::Specular), _) => Lobe::Specular,
+            (Some(Event::Transmission), _) => Lobe::Transmission,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lobe::Emission => "emission",
+            Lobe::DirectDiffuse => "direct_diffuse",
+            Lobe::IndirectDiffuse => "indirect_diffuse",
+            Lobe::Specular => "specular",
+            Lobe::Transmission => "transmission",
+        }
+    }
+}
+
+/// Radiance along one camera ray split by lobe, adding up to the full color.
+#[derive(Clone, Copy, Default)]
+pub struct LobeColors([Color; 5]);
+
+impl LobeColors {
+    pub fn add(&mut self, lobe: Lobe, color: Color) {
+        self.0[lobe as usize] += color;
+    }
+
+    pub fn total(&self) -> Color {
+        self.0.iter().sum()
+    }
+}
+
+impl Index<Lobe> for LobeColors {
+    type Output = Color;
+
+    fn index(&self, lobe: Lobe) -> &Color {
+        &self.0[lobe as usize]
+    }
+}
+
+impl AddAssign for LobeColors {
+    fn add_assign(&mut self, other: Self) {
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a += b;
+        }
+    }
+}
@@ -1,10 +1,33 @@
+mod adaptive;
+mod animation;
+mod ao;
+mod aov;
+mod background;
+mod bdpt;
 mod camera;
 mod color;
+mod debug;
+mod denoise;
+mod distribution;
+mod film;
+mod ies;
 mod instance;
+mod integrator;
+mod light;
+mod lpe;
 mod material;
 mod mesh;
+mod mlt;
 mod orthonormalbasis;
+mod photon;
+mod preview;
+mod sampler;
+mod sky;
+mod spectrum;
+mod stats;
 mod texture;
+mod tile;
+mod traversal;
 mod world;
 
 use bvh::{
@@ -16,7 +39,6 @@ use bvh::{
 use color::Color;
 use glam::{Quat, Vec3};
 use image::{ImageBuffer, Rgb};
-use rand::Rng;
 use rayon::prelude::*;
 use std::{
     borrow::Borrow, f32::consts::PI, fs::File, io::BufReader, rc::Rc, sync::Arc, time::Instant,
@@ -24,15 +46,28 @@ use std::{
 use texture::CheckerTex;
 
 use crate::{
-    camera::Camera,
+    animation::{Animation, CameraTrack, InstanceTrack, Track},
+    aov::Aov,
+    background::EnvMap,
+    bdpt::Bdpt,
+    camera::{Aperture, EquirectangularCamera, PerspectiveCamera},
     color::RGB,
+    denoise::Denoiser,
+    film::Filter,
     instance::Instance,
+    light::{DirectionalLight, SpotLight},
     material::{DiffuseLight, Normals},
     mesh::Mesh,
+    mlt::Metropolis,
+    photon::CausticPhotons,
+    preview::Orbit,
+    sampler::SamplerKind,
+    sky::Sky,
+    spectrum::SpectralPathTracer,
 };
 use crate::{
-    material::{Dielectric, Lambertian, Material, Metal, ToWithMat, WithMat},
-    world::World,
+    material::{Dielectric, Lambertian, Material, Metal, ToSurfaceWithMat, WithMat},
+    world::{RenderSettings, World},
 };
 use obj::{load_obj, Obj};
 
@@ -57,7 +92,7 @@ fn random_sphere_world() -> World {
     }
 
     for (sphere, mat) in &pairs {
-        world.objs.push(sphere.with_mat(mat.clone()))
+        world.objs.push(sphere.with_surface_mat(mat.clone()))
     }
 
     let mat_ground = Arc::new(Lambertian::from_tex(Arc::new(CheckerTex::from_colors(
@@ -66,15 +101,15 @@ fn random_sphere_world() -> World {
     ))));
     let ground = Sphere::new(Vec3::new(0., -1000.5, -1.), 1000.);
 
-    world.objs.push(ground.with_mat(mat_ground));
+    world.objs.push(ground.with_surface_mat(mat_ground));
 
     let glass = Arc::new(Dielectric::new(1.5));
     let glass_sphere = Sphere::new(Vec3::new(0., 1., 0.), 1.0);
-    world.objs.push(glass_sphere.with_mat(glass));
+    world.objs.push(glass_sphere.with_surface_mat(glass));
 
     let brown = Arc::new(Lambertian::new(Vec3::new(0.4, 0.2, 0.1)));
     let brown_sphere = Sphere::new(Vec3::new(-4., 1., 0.), 1.0);
-    world.objs.push(brown_sphere.with_mat(brown));
+    world.objs.push(brown_sphere.with_surface_mat(brown));
 
     let metal = Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0));
     let metal_sphere = Arc::new(Sphere::new(Vec3::ZERO, 1.));
@@ -84,7 +119,7 @@ fn random_sphere_world() -> World {
         Quat::IDENTITY,
         Vec3::new(1.0, 3.0, 1.0),
     );
-    world.objs.push(metal_sphere.with_mat(metal));
+    world.objs.push(metal_sphere.with_surface_mat(metal));
 
     world.build();
 
@@ -93,7 +128,10 @@ fn random_sphere_world() -> World {
 
 fn render_trimesh() {
     println!("Setup");
-    let height = 720;
+    let settings = RenderSettings {
+        height: 720,
+        ..Default::default()
+    };
     let origin = Vec3::new(3., 6., 13.);
     let lookat = Vec3::new(0., 0., 0.);
     let vfov = 50.;
@@ -112,48 +150,284 @@ fn render_trimesh() {
     );
     let mesh_2 = Instance::from_t(mesh.clone(), Vec3::new(5.5, 0., 0.));
     let sphere_mesh = Instance::from_t(Arc::new(sphere), Vec3::new(-10.5, 0., 0.));
-    world.objs.push(mesh_1.with_mat(mat.clone()));
-    world.objs.push(mesh_2.with_mat(metal.clone()));
-    world.objs.push(sphere_mesh.with_mat(metal));
+    world.objs.push(mesh_1.with_surface_mat(mat.clone()));
+    world.objs.push(mesh_2.with_surface_mat(metal.clone()));
+    world.objs.push(sphere_mesh.with_surface_mat(metal));
     world.build();
     for obj in &world.objs {
         dbg!(obj.node_index);
     }
 
-    world.render(
-        "two_spheres.png",
-        height,
-        origin,
-        lookat,
-        vfov,
-        Vec3::new(0.7, 0.8, 1.),
-        16. / 9.,
-    );
+    let camera = PerspectiveCamera::new(origin, lookat, Vec3::Y, vfov, 16. / 9., 0., 10.);
+    world.background = Arc::new(Vec3::new(0.7, 0.8, 1.));
+    world.render("two_spheres.png", &camera, &settings);
 }
 
 fn render_random_spheres() {
     println!("Setup");
-    let height = 480;
-    let world = random_sphere_world();
+    let settings = RenderSettings {
+        height: 480,
+        ..Default::default()
+    };
+    let mut world = random_sphere_world();
     let vfov = 20.;
 
     let origin = Vec3::new(13., 2., 3.);
     let lookat = Vec3::new(0., 0., 0.);
 
-    world.render(
-        "random_spheres.png",
-        height,
-        origin,
-        lookat,
-        vfov,
-        Vec3::new(0.7, 0.8, 1.),
+    let camera = PerspectiveCamera::new(origin, lookat, Vec3::Y, vfov, 16. / 9., 0., 10.);
+    world.background = Arc::new(Vec3::new(0.7, 0.8, 1.));
+    world.render("random_spheres.png", &camera, &settings);
+}
+
+fn render_depth_of_field() {
+    println!("Setup");
+    let settings = RenderSettings {
+        height: 480,
+        ..Default::default()
+    };
+    let mut world = random_sphere_world();
+    let vfov = 20.;
+
+    let origin = Vec3::new(13., 2., 3.);
+    let lookat = Vec3::new(0., 0., 0.);
+
+    let mut camera = PerspectiveCamera::new(origin, lookat, Vec3::Y, vfov, 16. / 9., 0.1, 10.);
+    camera.autofocus(&world);
+    camera.aperture = Aperture::Polygon {
+        blades: 6,
+        rotation: 0.,
+    };
+    world.background = Arc::new(Vec3::new(0.7, 0.8, 1.));
+    world.render("depth_of_field.png", &camera, &settings);
+}
+
+fn preview_random_spheres() {
+    println!("Setup");
+    let settings = RenderSettings {
+        height: 360,
+        tile_size: 16,
+        ..Default::default()
+    };
+    let mut world = random_sphere_world();
+    world.background = Arc::new(Vec3::new(0.7, 0.8, 1.));
+
+    let orbit = Orbit::new(Vec3::new(13., 2., 3.), Vec3::ZERO, 20., 16. / 9.);
+    preview::serve(&world, orbit, &settings, "127.0.0.1:8080");
+}
+
+fn render_env_spheres() {
+    println!("Setup");
+    let settings = RenderSettings {
+        height: 480,
+        ..Default::default()
+    };
+    let mut world = random_sphere_world();
+    world.background = Arc::new(
+        EnvMap::from_file("env.hdr")
+            .with_rotation(Quat::from_axis_angle(Vec3::Y, (90. as f32).to_radians()))
+            .with_intensity(1.5),
+    );
+
+    let camera = PerspectiveCamera::new(
+        Vec3::new(13., 2., 3.),
+        Vec3::ZERO,
+        Vec3::Y,
+        20.,
+        16. / 9.,
+        0.,
+        10.,
+    );
+    world.render("env_spheres.png", &camera, &settings);
+}
+
+fn render_sky_spheres() {
+    println!("Setup");
+    let settings = RenderSettings {
+        height: 480,
+        ..Default::default()
+    };
+    let mut world = random_sphere_world();
+    world.background = Arc::new(Sky::new(Vec3::new(1., 0.4, 0.5), 2.5));
+
+    let camera = PerspectiveCamera::new(
+        Vec3::new(13., 2., 3.),
+        Vec3::ZERO,
+        Vec3::Y,
+        20.,
+        16. / 9.,
+        0.,
+        10.,
+    );
+    world.render("sky_spheres.png", &camera, &settings);
+}
+
+fn render_dispersion() {
+    println!("Setup");
+    let settings = RenderSettings {
+        height: 480,
+        integrator: Arc::new(SpectralPathTracer),
+        ..Default::default()
+    };
+    let mut world = World::new(vec![]);
+    let floor = Arc::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
+    let bk7 = Arc::new(Dielectric::sellmeier(
+        [1.03961212, 0.231792344, 1.01046945],
+        [0.00600069867, 0.0200179144, 103.560653],
+    ));
+    let light = Arc::new(DiffuseLight::new(Vec3::splat(40.)));
+    world
+        .objs
+        .push(Sphere::new(Vec3::new(0., -1000., 0.), 1000.).with_surface_mat(floor));
+    world
+        .objs
+        .push(Sphere::new(Vec3::new(0., 1., 0.), 1.).with_surface_mat(bk7));
+    world
+        .objs
+        .push(Sphere::new(Vec3::new(-3., 5., -2.), 0.3).with_surface_mat(light));
+    world.build();
+
+    let camera = PerspectiveCamera::new(
+        Vec3::new(6., 3., 4.),
+        Vec3::new(0., 0.6, 0.),
+        Vec3::Y,
+        30.,
+        16. / 9.,
+        0.,
+        10.,
+    );
+    world.render("dispersion.png", &camera, &settings);
+}
+
+/// Glass ball on a floor lit by a small light, focusing it into a caustic.
+fn glass_caustic_world() -> World {
+    let mut world = World::new(vec![]);
+    let floor = Arc::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
+    let glass = Arc::new(Dielectric::new(1.5));
+    let light = Arc::new(DiffuseLight::new(Vec3::splat(40.)));
+    world
+        .objs
+        .push(Sphere::new(Vec3::new(0., -1000., 0.), 1000.).with_surface_mat(floor));
+    world
+        .objs
+        .push(Sphere::new(Vec3::new(0., 1., 0.), 1.).with_surface_mat(glass));
+    world
+        .objs
+        .push(Sphere::new(Vec3::new(-2., 4., -1.), 0.2).with_surface_mat(light));
+    world.build();
+    world
+}
+
+fn glass_caustic_camera() -> PerspectiveCamera {
+    PerspectiveCamera::new(
+        Vec3::new(6., 3., 4.),
+        Vec3::new(0., 0.6, 0.),
+        Vec3::Y,
+        30.,
+        16. / 9.,
+        0.,
+        10.,
+    )
+}
+
+fn render_bdpt_caustics() {
+    println!("Setup");
+    let settings = RenderSettings {
+        height: 480,
+        samples_per_px: 64,
+        integrator: Arc::new(Bdpt),
+        light_paths: true,
+        ..Default::default()
+    };
+    let world = glass_caustic_world();
+    world.render("bdpt_caustics.png", &glass_caustic_camera(), &settings);
+}
+
+fn render_photon_caustics() {
+    println!("Setup");
+    let world = glass_caustic_world();
+    let mut settings = RenderSettings {
+        height: 480,
+        samples_per_px: 64,
+        ..Default::default()
+    };
+    let photons = CausticPhotons::new(&world, 1_000_000, 0.05, 50, settings.seed);
+    settings.integrator = Arc::new(photons);
+    world.render("photon_caustics.png", &glass_caustic_camera(), &settings);
+}
+
+/// A light shut in a box that only lets it out through a slit along the
+/// floor, which is where Metropolis does better than tracing every pixel.
+fn render_mlt() {
+    println!("Setup");
+    let settings = RenderSettings {
+        height: 360,
+        ..Default::default()
+    };
+    let mut world = World::new(vec![]);
+    let cube = Arc::new(Mesh::from_file("cube.obj", false));
+    let white = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    let light = Arc::new(DiffuseLight::new(Vec3::splat(50.)));
+
+    world
+        .objs
+        .push(Sphere::new(Vec3::new(0., -1000., 0.), 1000.).with_surface_mat(white.clone()));
+    // Walls stop short of the floor, the lid sits on top
+    let walls = [
+        (Vec3::new(-1., 0.05, -1.), Vec3::new(2., 1.2, 0.05)),
+        (Vec3::new(-1., 0.05, 0.95), Vec3::new(2., 1.2, 0.05)),
+        (Vec3::new(-1., 0.05, -1.), Vec3::new(0.05, 1.2, 2.)),
+        (Vec3::new(0.95, 0.05, -1.), Vec3::new(0.05, 1.2, 2.)),
+        (Vec3::new(-1., 1.25, -1.), Vec3::new(2., 0.05, 2.)),
+    ];
+    for (translation, scale) in walls {
+        let wall = Instance::from_trs(cube.clone(), translation, Quat::IDENTITY, scale);
+        world.objs.push(wall.with_surface_mat(white.clone()));
+    }
+    world
+        .objs
+        .push(Sphere::new(Vec3::new(0., 0.6, 0.), 0.2).with_surface_mat(light));
+    world.build();
+
+    let camera = PerspectiveCamera::new(
+        Vec3::new(5., 3., 6.),
+        Vec3::new(0., 0.5, 0.),
+        Vec3::Y,
+        35.,
         16. / 9.,
+        0.,
+        10.,
     );
+    let mlt = Metropolis {
+        mutations_per_px: 256,
+        ..Default::default()
+    };
+    mlt.render(&world, "mlt_enclosure.png", &camera, &settings);
+}
+
+fn render_panorama() {
+    println!("Setup");
+    let settings = RenderSettings {
+        height: 512,
+        ..Default::default()
+    };
+    let mut world = random_sphere_world();
+
+    let camera = EquirectangularCamera::new(
+        Vec3::new(0., 2., 6.),
+        Vec3::new(0., 2., 0.),
+        Vec3::Y,
+    );
+    world.background = Arc::new(Vec3::new(0.7, 0.8, 1.));
+    world.render("panorama.png", &camera, &settings);
 }
 
 fn render_cubes() {
     println!("Setup");
-    let height = 480;
+    let settings = RenderSettings {
+        height: 480,
+        ..Default::default()
+    };
     let origin = Vec3::new(0., 7., 26.);
     let lookat = Vec3::new(0., 2., 0.);
     let vfov = 20.;
@@ -166,7 +440,7 @@ fn render_cubes() {
     ))));
     let ground = Sphere::new(Vec3::new(0., -1000.5, -1.), 1000.);
 
-    world.objs.push(ground.with_mat(mat_ground));
+    world.objs.push(ground.with_surface_mat(mat_ground));
 
     // let sphere = Sphere::new(Vec3::ZERO, 1.0);
     // let mat = Arc::new(Lambertian::new(Vec3::new(0.9, 0.1, 0.1)));
@@ -188,25 +462,172 @@ fn render_cubes() {
         Vec3::new(1.0, 1., 1.),
     );
     let sphere_mesh = Instance::from_t(cube, Vec3::new(-3., 0., 0.));
-    world.objs.push(mesh_1.with_mat(mat.clone()));
+    world.objs.push(mesh_1.with_surface_mat(mat.clone()));
     //world.objs.push(light_cube.with_mat(light));
     // world.objs.push(sphere_mesh.with_mat(metal));
     world.build();
 
-    world.render(
-        "cubes.png",
-        height,
-        origin,
-        lookat,
-        vfov,
-        Vec3::new(0.7, 0.8, 1.),
-        16. / 9.,
+    let camera = PerspectiveCamera::new(origin, lookat, Vec3::Y, vfov, 16. / 9., 0., 10.);
+    world.lights.push(Arc::new(SpotLight::new(
+        Vec3::new(4., 8., 4.),
+        Vec3::new(-1., 0., 0.),
+        Vec3::new(60., 55., 50.),
+        15.,
+        25.,
+    )));
+    world.lights.push(Arc::new(DirectionalLight::new(
+        Vec3::new(-1., -2., -1.),
+        Vec3::new(0.3, 0.3, 0.35),
+    )));
+    world.background = Arc::new(Vec3::new(0.7, 0.8, 1.));
+    world.render("cubes.png", &camera, &settings);
+}
+
+fn render_turntable() {
+    println!("Setup");
+    let settings = RenderSettings {
+        height: 480,
+        ..Default::default()
+    };
+    let mut world = World::new(vec![]);
+    let cube = Arc::new(Mesh::from_file("cube.obj", false));
+
+    let mat_ground = Arc::new(Lambertian::from_tex(Arc::new(CheckerTex::from_colors(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    ))));
+    let ground = Sphere::new(Vec3::new(0., -1000.5, -1.), 1000.);
+    world.objs.push(ground.with_surface_mat(mat_ground));
+
+    let red = Arc::new(Lambertian::new(Vec3::new(0.65, 0.1, 0.1)));
+    world
+        .objs
+        .push(Instance::from_t(cube.clone(), Vec3::ZERO).with_surface_mat(red));
+    world.build();
+
+    let frames = 48;
+    let fps = 24.;
+    let end = frames as f32 / fps;
+    let camera = CameraTrack::new(
+        Track::constant(Vec3::new(0., 7., 26.)).key(end, Vec3::new(0., 4., 14.)),
+        Track::constant(Vec3::new(0., 1., 0.)),
+        Track::constant(20.).key(end, 30.),
     );
+    let mut animation = Animation::new(camera, fps, frames);
+    animation.objects.push(Box::new(InstanceTrack::new(
+        1,
+        cube,
+        Track::constant(Vec3::new(-1., 0., -1.)),
+        Track::constant(Quat::IDENTITY)
+            .key(end / 2., Quat::from_axis_angle(Vec3::Y, PI))
+            .key(end, Quat::from_axis_angle(Vec3::Y, 2. * PI)),
+        Track::constant(Vec3::new(2., 2., 2.)),
+    )));
+
+    world.background = Arc::new(Vec3::new(0.7, 0.8, 1.));
+    animation.render(&mut world, "turntable", &settings, 16. / 9.);
 }
 
 fn cornell_box() {
     println!("Setup");
-    let height = 480;
+    let settings = RenderSettings {
+        height: 480,
+        ..Default::default()
+    };
+    let (world, camera) = cornell_box_scene();
+    world.render("cornell_box.png", &camera, &settings);
+}
+
+/// The Cornell box at a low sample count with every sampler, to compare
+/// their noise.
+fn render_samplers() {
+    println!("Setup");
+    let (world, camera) = cornell_box_scene();
+    let samplers = [
+        ("random", SamplerKind::Random),
+        ("stratified", SamplerKind::Stratified),
+        ("halton", SamplerKind::Halton),
+        ("sobol", SamplerKind::Sobol),
+        ("blue_noise", SamplerKind::BlueNoise),
+    ];
+    for (name, sampler) in samplers {
+        let settings = RenderSettings {
+            height: 480,
+            samples_per_px: 16,
+            sampler,
+            ..Default::default()
+        };
+        world.render(&format!("cornell_box_{}.png", name), &camera, &settings);
+    }
+}
+
+/// Adaptive sampling, writing how many samples each pixel took next to the
+/// image.
+fn render_adaptive() {
+    println!("Setup");
+    let (world, camera) = cornell_box_scene();
+    let settings = RenderSettings {
+        height: 480,
+        adaptive_threshold: 0.01,
+        sample_heatmap: true,
+        ..Default::default()
+    };
+    world.render("cornell_box_adaptive.png", &camera, &settings);
+}
+
+/// The Cornell box through each reconstruction filter.
+fn render_filters() {
+    println!("Setup");
+    let (world, camera) = cornell_box_scene();
+    let filters = [
+        ("gaussian", Filter::gaussian()),
+        ("mitchell", Filter::mitchell()),
+        ("blackman_harris", Filter::blackman_harris()),
+    ];
+    for (name, filter) in filters {
+        let settings = RenderSettings {
+            height: 480,
+            filter,
+            ..Default::default()
+        };
+        world.render(&format!("cornell_box_{}.png", name), &camera, &settings);
+    }
+}
+
+/// Writes every auxiliary pass of the Cornell box next to the image.
+fn render_aovs() {
+    println!("Setup");
+    let (world, camera) = cornell_box_scene();
+    let settings = RenderSettings {
+        height: 480,
+        aovs: vec![
+            Aov::Albedo,
+            Aov::Normal,
+            Aov::Depth,
+            Aov::Position,
+            Aov::ObjectId,
+            Aov::MaterialId,
+        ],
+        ..Default::default()
+    };
+    world.render("cornell_box_aovs.png", &camera, &settings);
+}
+
+/// The Cornell box at a low sample count, written both as traced and
+/// denoised.
+fn render_denoised() {
+    println!("Setup");
+    let (world, camera) = cornell_box_scene();
+    let settings = RenderSettings {
+        height: 480,
+        samples_per_px: 16,
+        denoise: Some(Denoiser::default()),
+        ..Default::default()
+    };
+    world.render("cornell_box_noisy.png", &camera, &settings);
+}
+
+fn cornell_box_scene() -> (World, PerspectiveCamera) {
     let origin = Vec3::new(278., 278., -800.);
     let lookat = Vec3::new(278., 278., 0.);
     let vfov = 40.;
@@ -218,7 +639,7 @@ fn cornell_box() {
     let red = Arc::new(Lambertian::new(Vec3::new(0.65, 0.1, 0.1)));
     let white = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
     let green = Arc::new(Lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
-    let light = Arc::new(DiffuseLight::new(Vec3::new(15., 15., 15.)));
+    let light = Arc::new(DiffuseLight::new(Vec3::new(15., 15., 15.)).one_sided());
 
     let left = Instance::from_trs(
         cube.clone(),
@@ -271,30 +692,22 @@ fn cornell_box() {
         Vec3::new(165., 330., 165.),
     );
     dbg!(left.aabb());
-    world.objs.push(left.with_mat(red.clone()));
-    world.objs.push(right.with_mat(green.clone()));
-    world.objs.push(back.with_mat(white.clone()));
-    world.objs.push(top.with_mat(white.clone()));
-    world.objs.push(bottom.with_mat(white.clone()));
-    world.objs.push(light_cube.with_mat(light));
-    world.objs.push(front_cube.with_mat(white.clone()));
-    world.objs.push(back_cube.with_mat(white.clone()));
+    world.objs.push(left.with_surface_mat(red.clone()));
+    world.objs.push(right.with_surface_mat(green.clone()));
+    world.objs.push(back.with_surface_mat(white.clone()));
+    world.objs.push(top.with_surface_mat(white.clone()));
+    world.objs.push(bottom.with_surface_mat(white.clone()));
+    world.objs.push(light_cube.with_surface_mat(light));
+    world.objs.push(front_cube.with_surface_mat(white.clone()));
+    world.objs.push(back_cube.with_surface_mat(white.clone()));
     world.build();
 
-    world.render(
-        "cornell_box.png",
-        height,
-        origin,
-        lookat,
-        vfov,
-        Vec3::new(0., 0., 0.),
-        1.,
-    );
+    let camera = PerspectiveCamera::new(origin, lookat, Vec3::Y, vfov, 1., 0., 10.);
+    (world, camera)
 }
 
 fn random() -> f32 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(0.0..1.0)
+    sampler::next_1d()
 }
 
 fn rand_range(min: f32, max: f32) -> f32 {
@@ -302,29 +715,30 @@ fn rand_range(min: f32, max: f32) -> f32 {
 }
 
 fn rand_in_sphere() -> Vec3 {
-    loop {
-        let x = Vec3::new(
-            rand_range(-1., 1.),
-            rand_range(-1., 1.),
-            rand_range(-1., 1.),
-        );
-        if x.length_squared() < 1. {
-            return x;
-        }
-    }
+    rand_unit_vector() * random().cbrt()
 }
 
+/// Concentric (Shirley-Chiu) mapping of the unit square onto the disk, which
+/// keeps the stratification of the samples fed to it.
 fn rand_in_disk() -> Vec3 {
-    loop {
-        let x = Vec3::new(rand_range(-1., 1.), rand_range(-1., 1.), 0.);
-        if x.length_squared() < 1. {
-            return x;
-        }
+    let u = 2. * random() - 1.;
+    let v = 2. * random() - 1.;
+    if u == 0. && v == 0. {
+        return Vec3::ZERO;
     }
+    let (r, theta) = if u.abs() > v.abs() {
+        (u, PI / 4. * (v / u))
+    } else {
+        (v, PI / 2. - PI / 4. * (u / v))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.)
 }
 
 fn rand_unit_vector() -> Vec3 {
-    rand_in_sphere().normalize()
+    let z = 1. - 2. * random();
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * random();
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
 fn rand_cos_dir() -> Vec3 {
@@ -350,6 +764,17 @@ fn pdf_vec(p: &Vec3) -> f32 {
     1. / (4. * PI)
 }
 
+/// Multiple importance sampling weight for a sample drawn with pdf `f`
+/// when another strategy could have drawn it with pdf `g`.
+fn power_heuristic(f: f32, g: f32) -> f32 {
+    let f2 = f * f;
+    let g2 = g * g;
+    if f2 + g2 == 0. {
+        return 0.;
+    }
+    f2 / (f2 + g2)
+}
+
 fn reflect(d: Vec3, n: Vec3) -> Vec3 {
     d - (2. * (d.dot(n)) * n)
 }
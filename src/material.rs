@@ -11,7 +11,8 @@ use crate::{
     color::Color,
     rand_in_sphere, rand_unit_vector, random, reflect, reflectance, refract,
     texture::{SolidTex, Texture},
-    world::Hittable, orthonormalbasis::OrthoNormalBasis,
+    world::{Hittable, Surface}, orthonormalbasis::OrthoNormalBasis,
+    ies::IesProfile, light::cone_falloff, spectrum,
 };
 
 pub trait Material: Sync + Send {
@@ -23,31 +24,88 @@ pub trait Material: Sync + Send {
         0.
     }
 
-    fn emit(&self, u: f32, v: f32, p: &Vec3) -> Color {
+    /// Radiance leaving the surface back along `ray`, towards its origin.
+    fn emit(&self, ray: &Ray, intersection: &Intersection) -> Color {
         Vec3::ZERO
     }
+
+    /// Rough average of `emit` over the surface, used to weigh emitters by
+    /// the power they give off.
+    fn average_emission(&self) -> Color {
+        Vec3::ZERO
+    }
+
+    /// Specular materials scatter in a single direction with pdf 1, so there
+    /// is no point sampling lights from them. `scattering_pdf` returns 1 as
+    /// well, so the two cancel in the path throughput and the attenuation
+    /// comes through as is.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Base color of the surface, what the albedo AOV shows.
+    fn albedo(&self, ray: &Ray, intersection: &Intersection) -> Color {
+        Vec3::ZERO
+    }
+
+    /// BSDF times the cosine towards `scattered`, for light leaving back
+    /// along `ray`. Used to connect paths, so specular materials never get
+    /// asked. The default fits materials that scatter `albedo` with
+    /// `scattering_pdf`.
+    fn eval(&self, ray: &Ray, intersection: &Intersection, scattered: &Ray) -> Color {
+        self.albedo(ray, intersection) * self.scattering_pdf(ray, intersection, scattered)
+    }
+
+    /// Scatters differently depending on `spectrum::wavelength`, so a
+    /// spectral path can only carry a single wavelength past it.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Clone)]
 pub struct WithMat {
     pub obj: Arc<(dyn Hittable)>,
+    /// Usually `obj` again, or `NotSampleable` for shapes that only know
+    /// how to be hit
+    pub surface: Arc<(dyn Surface)>,
     pub mat: Arc<(dyn Material)>,
     pub node_index: usize,
+    /// Chance of this object being picked when sampling emitters, set by
+    /// `World::build`
+    pub light_pmf: f32,
+    /// Position in `World::objs`, set by `World::build`
+    pub object_id: usize,
+    /// Same for every object sharing `mat`, set by `World::build`
+    pub material_id: usize,
 }
 
 impl WithMat {
+    /// Works for any shape, but one that can't be sampled as a light.
     pub fn new(obj: Arc<(dyn Hittable)>, mat: Arc<(dyn Material)>) -> Self {
+        Self::with_surface(obj, Arc::new(NotSampleable), mat)
+    }
+
+    pub fn with_surface(
+        obj: Arc<(dyn Hittable)>,
+        surface: Arc<(dyn Surface)>,
+        mat: Arc<(dyn Material)>,
+    ) -> Self {
         Self {
             obj,
+            surface,
             mat,
             node_index: 0,
+            light_pmf: 0.,
+            object_id: 0,
+            material_id: 0,
         }
     }
 }
 
 impl Material for WithMat {
-    fn emit(&self, u: f32, v: f32, p: &Vec3) -> Color {
-        self.mat.emit(u, v, p)
+    fn emit(&self, ray: &Ray, intersection: &Intersection) -> Color {
+        self.mat.emit(ray, intersection)
     }
 
     fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Ray, Color, f32)> {
@@ -57,6 +115,26 @@ impl Material for WithMat {
     fn scattering_pdf(&self, ray: &Ray, intersection: &Intersection, scattered: &Ray) -> f32 {
         self.mat.scattering_pdf(ray, intersection, scattered)
     }
+
+    fn average_emission(&self) -> Color {
+        self.mat.average_emission()
+    }
+
+    fn is_specular(&self) -> bool {
+        self.mat.is_specular()
+    }
+
+    fn albedo(&self, ray: &Ray, intersection: &Intersection) -> Color {
+        self.mat.albedo(ray, intersection)
+    }
+
+    fn eval(&self, ray: &Ray, intersection: &Intersection, scattered: &Ray) -> Color {
+        self.mat.eval(ray, intersection, scattered)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.mat.is_dispersive()
+    }
 }
 
 impl IntersectionRay for WithMat {
@@ -92,13 +170,34 @@ pub trait ToWithMat {
 
 impl<T> ToWithMat for T
 where
-    T: Hittable + Sync + 'static,
+    T: Hittable + 'static,
 {
     fn with_mat(self, mat: Arc<(dyn Material)>) -> WithMat {
         WithMat::new(Arc::new(self), mat)
     }
 }
 
+/// `with_mat` for shapes that are also a `Surface`, so they can be sampled
+/// as lights and show up properly in the debug views.
+pub trait ToSurfaceWithMat {
+    fn with_surface_mat(self, mat: Arc<(dyn Material)>) -> WithMat;
+}
+
+impl<T> ToSurfaceWithMat for T
+where
+    T: Hittable + Surface + 'static,
+{
+    fn with_surface_mat(self, mat: Arc<(dyn Material)>) -> WithMat {
+        let obj = Arc::new(self);
+        WithMat::with_surface(obj.clone(), obj, mat)
+    }
+}
+
+/// `Surface` of shapes that don't implement it.
+pub struct NotSampleable;
+
+impl Surface for NotSampleable {}
+
 pub struct Lambertian {
     pub albedo: Arc<dyn Texture>,
 }
@@ -145,8 +244,14 @@ impl Material for Lambertian {
             cosine / PI
         }
     }
+
+    fn albedo(&self, ray: &Ray, intersection: &Intersection) -> Color {
+        let hit = ray.at(intersection.distance);
+        self.albedo.value(intersection.u, intersection.v, &hit)
+    }
 }
 
+/// Mirror, blurred by `fuzz`. Scatters specularly, see `is_specular`.
 pub struct Metal {
     pub albedo: Vec3,
     pub fuzz: f32,
@@ -159,85 +264,250 @@ impl Metal {
 }
 
 impl Material for Metal {
-    // fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Ray, Color)> {
-    //     let reflected = reflect(ray.direction, intersection.norm) + (self.fuzz * rand_in_sphere());
-    //     if reflected.dot(intersection.norm) > 0. {
-    //         Some((
-    //             Ray::new(ray.at(intersection.distance), reflected),
-    //             self.albedo,
-    //         ))
-    //     } else {
-    //         None
-    //     }
-    // }
+    fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Ray, Color, f32)> {
+        let reflected = reflect(ray.direction, intersection.norm) + (self.fuzz * rand_in_sphere());
+        if reflected.dot(intersection.norm) > 0. {
+            Some((
+                Ray::new(ray.at(intersection.distance), reflected),
+                self.albedo,
+                1.,
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, _intersection: &Intersection, _scattered: &Ray) -> f32 {
+        1.
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, _ray: &Ray, _intersection: &Intersection) -> Color {
+        self.albedo
+    }
 }
 
+/// How a dielectric's index of refraction varies with the wavelength `λ`,
+/// in micrometers like the coefficients are usually published in.
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    None,
+    /// n = a + b / λ²
+    Cauchy { a: f32, b: f32 },
+    /// n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// Index of refraction at `wavelength` in nanometers, None when it
+    /// doesn't vary.
+    pub fn ior(&self, wavelength: f32) -> Option<f32> {
+        let l2 = (wavelength * 1e-3).powi(2);
+        match *self {
+            Dispersion::None => None,
+            Dispersion::Cauchy { a, b } => Some(a + b / l2),
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>();
+                Some(n2.max(1.).sqrt())
+            }
+        }
+    }
+}
+
+/// Wavelength of the sodium D line, where glass makers quote the index of
+/// refraction
+const D_LINE: f32 = 589.3;
+
+/// Glass and the like, reflecting or refracting by Fresnel. Scatters
+/// specularly, see `is_specular`.
 pub struct Dielectric {
+    /// Used when rendering in RGB, or at every wavelength without
+    /// `dispersion`
     pub index_of_refraction: f32,
+    pub dispersion: Dispersion,
 }
 
 impl Dielectric {
     pub fn new(index_of_refraction: f32) -> Self {
         Self {
             index_of_refraction,
+            dispersion: Dispersion::None,
         }
     }
+
+    /// Glass following Cauchy's equation, e.g. `a` 1.5046 and `b` 0.0042
+    /// for BK7.
+    pub fn cauchy(a: f32, b: f32) -> Self {
+        Self::dispersive(Dispersion::Cauchy { a, b })
+    }
+
+    /// Glass following the Sellmeier equation, e.g. BK7 with `b`
+    /// [1.0396, 0.2318, 1.0105] and `c` [0.0060, 0.0200, 103.56].
+    pub fn sellmeier(b: [f32; 3], c: [f32; 3]) -> Self {
+        Self::dispersive(Dispersion::Sellmeier { b, c })
+    }
+
+    fn dispersive(dispersion: Dispersion) -> Self {
+        Self {
+            index_of_refraction: dispersion.ior(D_LINE).unwrap_or(1.5),
+            dispersion,
+        }
+    }
+
+    /// Index of refraction for the wavelength being traced.
+    fn ior(&self) -> f32 {
+        spectrum::wavelength()
+            .and_then(|wavelength| self.dispersion.ior(wavelength))
+            .unwrap_or(self.index_of_refraction)
+    }
 }
 
 impl Material for Dielectric {
-    // fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Ray, Color)> {
-    //     let attenuation = Color::new(1.0, 1.0, 1.0);
-    //     let refraction_ratio = if intersection.back_face {
-    //         self.index_of_refraction
-    //     } else {
-    //         1.0 / self.index_of_refraction
-    //     };
-    //     let cos_theta = (-ray.direction).dot(intersection.norm).min(1.);
-    //     let sin_theta = (1. - cos_theta * cos_theta).sqrt();
-    //     let direction = if refraction_ratio * sin_theta > 1.
-    //         || reflectance(cos_theta, refraction_ratio) > random()
-    //     {
-    //         reflect(ray.direction, intersection.norm)
-    //     } else {
-    //         refract(ray.direction, intersection.norm, refraction_ratio)
-    //     };
-
-    //     Some((
-    //         Ray::new(ray.at(intersection.distance), direction),
-    //         attenuation,
-    //     ))
-    // }
+    fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Ray, Color, f32)> {
+        let attenuation = Color::new(1.0, 1.0, 1.0);
+        let index_of_refraction = self.ior();
+        let refraction_ratio = if intersection.back_face {
+            index_of_refraction
+        } else {
+            1.0 / index_of_refraction
+        };
+        let cos_theta = (-ray.direction).dot(intersection.norm).min(1.);
+        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        let direction = if refraction_ratio * sin_theta > 1.
+            || reflectance(cos_theta, refraction_ratio) > random()
+        {
+            reflect(ray.direction, intersection.norm)
+        } else {
+            refract(ray.direction, intersection.norm, refraction_ratio)
+        };
+
+        Some((
+            Ray::new(ray.at(intersection.distance), direction),
+            attenuation,
+            1.,
+        ))
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, _intersection: &Intersection, _scattered: &Ray) -> f32 {
+        1.
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, _ray: &Ray, _intersection: &Intersection) -> Color {
+        Color::ONE
+    }
+
+    fn is_dispersive(&self) -> bool {
+        !matches!(self.dispersion, Dispersion::None)
+    }
 }
 
+/// Shows the shading normal as a color, see `DebugMode` for views that
+/// don't need the scene's materials changed.
 pub struct Normals();
 
 impl Material for Normals {
-    // fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Ray, Color)> {
-    //     let mut scatter_direction = intersection.norm + rand_unit_vector();
+    fn emit(&self, _ray: &Ray, intersection: &Intersection) -> Color {
+        (intersection.norm + 1.) * 0.5
+    }
+}
 
-    //     if scatter_direction.abs().min_element() < 1e-6 {
-    //         scatter_direction = intersection.norm
-    //     }
-    //     let hit = ray.at(intersection.distance);
+/// How the radiance of an emitter varies with the angle away from its
+/// surface normal.
+#[derive(Clone)]
+pub enum EmissionProfile {
+    Diffuse,
+    /// Full strength inside the inner cone falling off to nothing at the
+    /// outer one, stored as cosines
+    Spot { cos_inner: f32, cos_outer: f32 },
+    Ies(Arc<IesProfile>),
+}
+
+impl EmissionProfile {
+    pub fn spot(inner_angle: f32, outer_angle: f32) -> Self {
+        EmissionProfile::Spot {
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+        }
+    }
 
-    //     let ray = Ray::new(hit, scatter_direction);
-    //     let albedo = (intersection.norm + 1.) * 0.5;
-    //     Some((ray, albedo))
-    // }
+    /// `dir` is the direction light leaves in, relative to the basis of the
+    /// surface normal.
+    pub fn scale(&self, dir: Vec3) -> f32 {
+        match self {
+            EmissionProfile::Diffuse => 1.,
+            EmissionProfile::Spot {
+                cos_inner,
+                cos_outer,
+            } => cone_falloff(dir.z, *cos_inner, *cos_outer),
+            EmissionProfile::Ies(ies) => {
+                let theta = dir.z.clamp(-1., 1.).acos().to_degrees();
+                let phi = dir.y.atan2(dir.x).to_degrees();
+                ies.value(theta, phi)
+            }
+        }
+    }
+
+    /// Cosine weighted average of `scale` over the hemisphere.
+    pub fn average(&self) -> f32 {
+        if let EmissionProfile::Diffuse = self {
+            return 1.;
+        }
+        let n = 64;
+        let mut total = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                // Stratified cosine weighted directions
+                let r1 = (i as f32 + 0.5) / n as f32;
+                let r2 = (j as f32 + 0.5) / n as f32;
+                let phi = 2. * PI * r1;
+                let dir = Vec3::new(
+                    phi.cos() * r2.sqrt(),
+                    phi.sin() * r2.sqrt(),
+                    (1. - r2).sqrt(),
+                );
+                total += self.scale(dir);
+            }
+        }
+        total / (n * n) as f32
+    }
 }
 
 pub struct DiffuseLight {
     pub albedo: Arc<dyn Texture>,
+    pub two_sided: bool,
+    pub profile: EmissionProfile,
 }
 
 impl DiffuseLight {
     pub fn new(albedo: Vec3) -> Self {
         let albedo = Arc::new(SolidTex::new(albedo));
-        Self { albedo }
+        Self::from_tex(albedo)
     }
 
     pub fn from_tex(albedo: Arc<dyn Texture>) -> Self {
-        Self { albedo }
+        Self {
+            albedo,
+            two_sided: true,
+            profile: EmissionProfile::Diffuse,
+        }
+    }
+
+    /// Only emit from the front face.
+    pub fn one_sided(mut self) -> Self {
+        self.two_sided = false;
+        self
+    }
+
+    pub fn with_profile(mut self, profile: EmissionProfile) -> Self {
+        self.profile = profile;
+        self
     }
 }
 
@@ -246,7 +516,26 @@ impl Material for DiffuseLight {
         None
     }
 
-    fn emit(&self, u: f32, v: f32, p: &Vec3) -> Color {
-        self.albedo.value(u, v, p)
+    fn emit(&self, ray: &Ray, intersection: &Intersection) -> Color {
+        if intersection.back_face && !self.two_sided {
+            return Vec3::ZERO;
+        }
+        let p = ray.at(intersection.distance);
+        let uvw = OrthoNormalBasis::from_w(&intersection.norm);
+        let out = -ray.direction;
+        let local = Vec3::new(out.dot(uvw.u()), out.dot(uvw.v()), out.dot(uvw.w()));
+        self.albedo.value(intersection.u, intersection.v, &p) * self.profile.scale(local)
+    }
+
+    fn average_emission(&self) -> Color {
+        let sides = if self.two_sided { 2. } else { 1. };
+        self.albedo.average() * self.profile.average() * sides
+    }
+
+    fn albedo(&self, ray: &Ray, intersection: &Intersection) -> Color {
+        let p = ray.at(intersection.distance);
+        self.albedo
+            .value(intersection.u, intersection.v, &p)
+            .min(Color::ONE)
     }
 }
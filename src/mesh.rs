@@ -7,15 +7,24 @@ use bvh::{
     ray::{Intersection, IntersectionRay, Ray},
     Real, Triangle,
 };
-use glam::Vec3;
+use glam::{Mat4, Vec3};
 use itertools::Itertools;
 use obj::{load_obj, Obj, Position};
 
+use crate::{
+    distribution::Distribution1D,
+    random, stats,
+    traversal::Traversal,
+    world::{Surface, SurfaceSample},
+};
+
 pub struct Mesh {
     pub triangles: Vec<Indexed<RefTri>>,
     pub vertices: Arc<Vec<Vec3>>,
     pub normals: Arc<Vec<Vec3>>,
     bvh: BVH,
+    area: f32,
+    area_distribution: Distribution1D,
 }
 
 pub struct RefTri {
@@ -48,6 +57,15 @@ impl RefTri {
     }
 }
 
+impl RefTri {
+    pub fn area(&self, transform: &Mat4) -> f32 {
+        let a = transform.transform_point3(self.a_pos());
+        let b = transform.transform_point3(self.b_pos());
+        let c = transform.transform_point3(self.c_pos());
+        (b - a).cross(c - a).length() / 2.
+    }
+}
+
 impl Bounded for RefTri {
     fn aabb(&self) -> AABB {
         AABB::empty()
@@ -87,6 +105,8 @@ impl Mesh {
             bvh,
             normals: Arc::new(vec![]),
             vertices: Arc::new(vec![]),
+            area: 0.,
+            area_distribution: Distribution1D::new(vec![]),
         }
     }
 
@@ -147,7 +167,14 @@ impl Mesh {
     }
 
     pub fn rebuild(&mut self) {
-        self.bvh.rebuild(&mut self.triangles)
+        self.bvh.rebuild(&mut self.triangles);
+        let areas: Vec<f32> = self
+            .triangles
+            .iter()
+            .map(|tri| tri.obj.area(&Mat4::IDENTITY))
+            .collect();
+        self.area = areas.iter().sum();
+        self.area_distribution = Distribution1D::new(areas);
     }
 }
 
@@ -169,23 +196,85 @@ impl IntersectionRay for Mesh {
         //     }
         // })
 
-        self.bvh
-            .traverse_iterator(ray, &self.triangles)
-            .fold(None, |hit, tri| {
-                if let Some(inter) = tri.intersects_ray(ray, t_min, t_max) {
-                    if let Some(last_inter) = hit {
-                        if inter.distance < last_inter.distance {
-                            Some(inter)
-                        } else {
-                            Some(last_inter)
-                        }
-                    } else {
+        let mut traversal = Traversal::new(&self.bvh, ray, &self.triangles);
+        let hit = traversal.by_ref().fold(None, |hit, tri| {
+            if let Some(inter) = tri.intersects_ray(ray, t_min, t_max) {
+                if let Some(last_inter) = hit {
+                    if inter.distance < last_inter.distance {
                         Some(inter)
+                    } else {
+                        Some(last_inter)
                     }
                 } else {
-                    hit
+                    Some(inter)
                 }
+            } else {
+                hit
+            }
+        });
+        stats::count_bvh_nodes(traversal.nodes_visited);
+        hit
+    }
+}
+
+impl Surface for Mesh {
+    fn area(&self, transform: &Mat4) -> f32 {
+        self.triangles.iter().map(|tri| tri.obj.area(transform)).sum()
+    }
+
+    fn sample_surface(&self) -> Option<SurfaceSample> {
+        if self.area <= 0. {
+            return None;
+        }
+        let (_, _, idx) = self.area_distribution.sample_continuous(random());
+        let tri = &self.triangles[idx].obj;
+        let su = random().sqrt();
+        let b0 = 1. - su;
+        let b1 = random() * su;
+        let p = b0 * tri.a_pos() + b1 * tri.b_pos() + (1. - b0 - b1) * tri.c_pos();
+        let n = calc_normal(tri.a_pos(), tri.b_pos(), tri.c_pos());
+        // Hits weigh `b` by u and `c` by v
+        Some(SurfaceSample {
+            p,
+            n,
+            u: b1,
+            v: 1. - b0 - b1,
+            pdf: 1. / self.area,
+        })
+    }
+
+    fn surface_pdf(&self, _p: Vec3, _n: Vec3) -> f32 {
+        if self.area <= 0. {
+            return 0.;
+        }
+        1. / self.area
+    }
+
+    fn geometric_normal(&self, ray: &Ray, intersection: &Intersection) -> Vec3 {
+        // The hit doesn't remember its triangle, so find it again
+        let closest = self
+            .bvh
+            .traverse_iterator(ray, &self.triangles)
+            .filter_map(|tri| {
+                let inter = tri.intersects_ray(ray, 0., f32::INFINITY)?;
+                Some((tri, inter.distance))
             })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        match closest {
+            Some((tri, _)) => {
+                let n = calc_normal(tri.obj.a_pos(), tri.obj.b_pos(), tri.obj.c_pos());
+                if n.dot(ray.direction) > 0. {
+                    -n
+                } else {
+                    n
+                }
+            }
+            None => intersection.norm,
+        }
+    }
+
+    fn triangles_tested(&self, ray: &Ray) -> usize {
+        self.bvh.traverse_iterator(ray, &self.triangles).count()
     }
 }
 
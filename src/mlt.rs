@@ -0,0 +1,217 @@
+use std::{cell::RefCell, rc::Rc, time::Instant};
+
+use image::ImageBuffer;
+use rayon::prelude::*;
+
+use crate::{
+    camera::Camera,
+    color::{Color, RGB},
+    distribution::AliasTable,
+    film::Film,
+    random,
+    sampler::{self, mix, MltSampler, Pcg32},
+    world::{RenderSettings, World},
+};
+
+/// Primary sample space Metropolis light transport (PSSMLT) on top of
+/// `settings.integrator`, the path tracer by default.
+///
+/// A path is the sequence of random numbers the integrator draws for it, the
+/// first two of which pick the point on the film. Chains of mutated
+/// sequences then visit paths in proportion to their brightness, so light
+/// that only gets in through a gap or around several corners is found once
+/// and explored, instead of being stumbled upon by every pixel on its own.
+/// The price is noise that is blotchy and correlated rather than even.
+pub struct Metropolis {
+    /// Paths traced up front to normalize the image and start the chains
+    pub bootstrap_samples: usize,
+    pub chains: usize,
+    /// Mutations per pixel on average, the equivalent of samples per pixel
+    pub mutations_per_px: usize,
+    /// Standard deviation of small mutations, in primary sample space
+    pub sigma: f32,
+    /// Chance of a mutation drawing all new numbers instead
+    pub large_step_probability: f32,
+}
+
+impl Default for Metropolis {
+    fn default() -> Self {
+        Self {
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            mutations_per_px: 100,
+            sigma: 0.01,
+            large_step_probability: 0.3,
+        }
+    }
+}
+
+/// Chains run in parallel at once, their splats are kept until the whole
+/// batch is done
+const CHAINS_PER_BATCH: usize = 64;
+
+impl Metropolis {
+    /// Renders like `World::render`, taking the integrator, bounces, seed and
+    /// size from `settings`. The other settings are for per pixel sampling
+    /// and don't apply.
+    pub fn render(&self, world: &World, path: &str, camera: &dyn Camera, settings: &RenderSettings) {
+        let height = settings.height;
+        let width = (height as f32 * camera.aspect_ratio()) as usize;
+        let sampler_seed = |i: usize| mix(settings.seed ^ mix(i as u64));
+
+        println!("Begin Tracing");
+        let now = Instant::now();
+
+        // Average brightness over primary sample space, what the chains'
+        // unit brightness splats get scaled back up by
+        let weights: Vec<f32> = (0..self.bootstrap_samples)
+            .into_par_iter()
+            .map(|i| {
+                sampler::set_sampler(Box::new(MltSampler::new(
+                    sampler_seed(i),
+                    self.sigma,
+                    self.large_step_probability,
+                )));
+                brightness(trace(world, camera, settings, width, height).2)
+            })
+            .collect();
+        let b = weights.iter().sum::<f32>() / self.bootstrap_samples.max(1) as f32;
+        println!("Bootstrap brightness {} in {} ms", b, now.elapsed().as_millis());
+
+        let mut film = Film::new(width, height);
+        if b > 0. {
+            let table = AliasTable::new(&weights);
+            let total = self.mutations_per_px * width * height;
+            // Splats are added in chain order so the result doesn't depend on
+            // scheduling, a batch of chains at a time to bound the memory
+            for batch in (0..self.chains).step_by(CHAINS_PER_BATCH) {
+                let splats: Vec<Vec<(f32, f32, Color)>> = (batch
+                    ..(batch + CHAINS_PER_BATCH).min(self.chains))
+                    .into_par_iter()
+                    .map(|chain| {
+                        let mutations =
+                            total / self.chains + usize::from(chain < total % self.chains);
+                        self.run_chain(
+                            world,
+                            camera,
+                            settings,
+                            &table,
+                            sampler_seed,
+                            chain,
+                            mutations,
+                            b,
+                            (width, height),
+                        )
+                    })
+                    .collect();
+                for (x, y, color) in splats.into_iter().flatten() {
+                    film.add_splat(x, y, color);
+                }
+            }
+            film.samples = total;
+        }
+        println!("Done Tracing in {} ms", now.elapsed().as_millis());
+
+        let image = ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+            let i = (x + (y * width as u32)) as usize;
+            film.color(i).to_px(1)
+        });
+        image.save(path).expect("Image to save");
+        println!("Image written to {}", path);
+    }
+
+    /// Runs one Markov chain for `mutations` steps, starting from a bootstrap
+    /// path picked by brightness, and returns the splats it made.
+    #[allow(clippy::too_many_arguments)]
+    fn run_chain(
+        &self,
+        world: &World,
+        camera: &dyn Camera,
+        settings: &RenderSettings,
+        table: &AliasTable,
+        sampler_seed: impl Fn(usize) -> u64,
+        chain: usize,
+        mutations: usize,
+        b: f32,
+        (width, height): (usize, usize),
+    ) -> Vec<(f32, f32, Color)> {
+        let mut rng = Pcg32::new(mix(settings.seed ^ mix(chain as u64)), 1);
+        // Black paths have no chance in theory, but rounding can leave the
+        // table some
+        let start = loop {
+            let (start, pmf) = table.sample(rng.next_f32());
+            if pmf > 0. {
+                break start;
+            }
+        };
+        // Seeded like the bootstrap sampler, its first sample is that path
+        let mlt = Rc::new(RefCell::new(MltSampler::new(
+            sampler_seed(start),
+            self.sigma,
+            self.large_step_probability,
+        )));
+        sampler::set_sampler(Box::new(mlt.clone()));
+
+        let mut splats = Vec::with_capacity(2 * mutations);
+        let mut current = trace(world, camera, settings, width, height);
+        let mut current_brightness = brightness(current.2);
+        for _ in 0..mutations {
+            mlt.borrow_mut().start_iteration();
+            let proposed = trace(world, camera, settings, width, height);
+            let proposed_brightness = brightness(proposed.2);
+            let accept = if current_brightness > 0. {
+                (proposed_brightness / current_brightness).min(1.)
+            } else {
+                1.
+            };
+
+            // Both paths get their expected share, which is less noisy than
+            // only splatting whichever one the chain ends up at. Black paths
+            // have nothing to give, and would divide 0 by 0
+            if accept > 0. && proposed_brightness > 0. {
+                let color = proposed.2 * accept * b / proposed_brightness;
+                splats.push((proposed.0, proposed.1, color));
+            }
+            if accept < 1. && current_brightness > 0. {
+                let color = current.2 * (1. - accept) * b / current_brightness;
+                splats.push((current.0, current.1, color));
+            }
+
+            if rng.next_f32() < accept {
+                current = proposed;
+                current_brightness = proposed_brightness;
+                mlt.borrow_mut().accept();
+            } else {
+                mlt.borrow_mut().reject();
+            }
+        }
+        splats
+    }
+}
+
+/// Scalar the chains are distributed by.
+fn brightness(color: Color) -> f32 {
+    let y = color.luminance();
+    if y.is_finite() {
+        y.max(0.)
+    } else {
+        0.
+    }
+}
+
+/// Traces the path the current random numbers describe, returning where it
+/// lands on the film and the light it carries.
+fn trace(
+    world: &World,
+    camera: &dyn Camera,
+    settings: &RenderSettings,
+    width: usize,
+    height: usize,
+) -> (f32, f32, Color) {
+    sampler::start_sample(0, 0);
+    let fx = random() * width as f32;
+    let fy = random() * height as f32;
+    let ray = camera.get_ray(fx / width as f32, 1. - fy / height as f32);
+    let color = settings.integrator.li(world, &ray, settings.max_bounces).total();
+    (fx, fy, color)
+}
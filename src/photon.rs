@@ -0,0 +1,318 @@
+use std::{cmp::Ordering, f32::consts::PI};
+
+use bvh::ray::{Intersection, Ray};
+use glam::Vec3;
+use rayon::prelude::*;
+
+use crate::{
+    bdpt::{emission_dir, emission_pdf},
+    color::Color,
+    integrator::{first_event, Integrator},
+    lpe::{Lobe, LobeColors},
+    material::{Material, WithMat},
+    power_heuristic,
+    sampler::{self, mix, RandomSampler},
+    stats,
+    world::World,
+};
+
+/// Light left on a surface by a photon travelling along `dir`.
+#[derive(Clone, Copy)]
+pub struct Photon {
+    pub p: Vec3,
+    pub dir: Vec3,
+    pub power: Color,
+}
+
+/// Balanced kd-tree over photons, kept in a single array: the middle photon
+/// of every range splits the rest of it along `axes` of that same index.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    /// Calls `f` on every photon closer to `p` than `radius`.
+    pub fn for_each_within(&self, p: Vec3, radius: f32, mut f: impl FnMut(&Photon)) {
+        gather(&self.photons, &self.axes, p, radius * radius, &mut f);
+    }
+}
+
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let (min, max) = photons.iter().fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), photon| (min.min(photon.p), max.max(photon.p)),
+    );
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| {
+        a.p[axis].partial_cmp(&b.p[axis]).unwrap_or(Ordering::Equal)
+    });
+    axes[mid] = axis as u8;
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+fn gather(photons: &[Photon], axes: &[u8], p: Vec3, radius2: f32, f: &mut impl FnMut(&Photon)) {
+    if photons.is_empty() {
+        return;
+    }
+    let mid = photons.len() / 2;
+    let photon = &photons[mid];
+    let axis = axes[mid] as usize;
+    let d = p[axis] - photon.p[axis];
+    let (near, far) = if d < 0. {
+        ((&photons[..mid], &axes[..mid]), (&photons[mid + 1..], &axes[mid + 1..]))
+    } else {
+        ((&photons[mid + 1..], &axes[mid + 1..]), (&photons[..mid], &axes[..mid]))
+    };
+
+    gather(near.0, near.1, p, radius2, f);
+    if d * d < radius2 {
+        if (photon.p - p).length_squared() < radius2 {
+            f(photon);
+        }
+        gather(far.0, far.1, p, radius2, f);
+    }
+}
+
+/// Path tracer that leaves caustics, light focused onto diffuse surfaces by
+/// mirrors and glass, to a photon map. The path tracer finds those paths
+/// only when a diffuse bounce happens to scatter through the glass straight
+/// into a light, so small lights behind glass come out as fireflies, while
+/// the photons land right where the focused light goes.
+///
+/// The map is shot once up front, so the caustics are slightly blurred by
+/// `radius` and don't get any sharper with more samples.
+pub struct CausticPhotons {
+    map: PhotonMap,
+    pub radius: f32,
+    /// Bounces the photons were followed for
+    max_depth: usize,
+}
+
+impl CausticPhotons {
+    /// Shoots `photons` from the emitters of `world`, which has to be built
+    /// already. Photons that reach a diffuse surface after at least one
+    /// specular bounce, within `max_depth` bounces, are kept. The same
+    /// `seed` shoots the same photons, pass `RenderSettings::seed` to keep
+    /// renders reproducible.
+    pub fn new(world: &World, photons: usize, radius: f32, max_depth: usize, seed: u64) -> Self {
+        // Mixed twice to stay apart from the camera samples and the AOVs
+        let seed = mix(mix(seed));
+        let kept: Vec<Photon> = (0..photons)
+            .into_par_iter()
+            .map_init(
+                || sampler::set_sampler(Box::new(RandomSampler::new(seed))),
+                |_, i| {
+                    sampler::start_sample(0, i);
+                    shoot(world, max_depth)
+                },
+            )
+            .flatten()
+            .map(|photon| Photon {
+                power: photon.power / photons as f32,
+                ..photon
+            })
+            .collect();
+        Self {
+            map: PhotonMap::new(kept),
+            radius,
+            max_depth,
+        }
+    }
+
+    /// Caustic light leaving the diffuse hit back along `ray`.
+    fn caustics(&self, ray: &Ray, intersection: &Intersection, obj: &WithMat) -> Color {
+        let hit = ray.at(intersection.distance);
+        let mut sum = Color::ZERO;
+        self.map.for_each_within(hit, self.radius, |photon| {
+            let cos = intersection.norm.dot(-photon.dir);
+            if cos > 0. {
+                let f = obj.eval(ray, intersection, &Ray::new(hit, -photon.dir)) / cos;
+                sum += f * photon.power;
+            }
+        });
+        sum / (PI * self.radius * self.radius)
+    }
+}
+
+/// Follows one photon from an emitter, returning where it lands if that is a
+/// caustic.
+fn shoot(world: &World, max_depth: usize) -> Option<Photon> {
+    let (light, sample) = world.sample_emitter()?;
+    let (p, n) = (sample.p, sample.n);
+    let dir = emission_dir(n);
+    let pdf_dir = emission_pdf(n, dir);
+    if pdf_dir <= 0. {
+        return None;
+    }
+    let back_face = n.dot(dir) < 0.;
+    let norm = if back_face { -n } else { n };
+    let emit = light.emit(
+        &Ray::new(p + dir, -dir),
+        &Intersection::new(1., sample.u, sample.v, norm, back_face),
+    );
+    let mut power = emit * n.dot(dir).abs() / (sample.pdf * pdf_dir);
+    let mut ray = Ray::new(p, dir);
+    let mut specular = false;
+
+    for _ in 0..max_depth {
+        if power == Color::ZERO {
+            return None;
+        }
+        let (obj, intersection) = world.first_intersection(ray, 0.00001, f32::INFINITY)?;
+        if !obj.is_specular() {
+            return if specular {
+                Some(Photon {
+                    p: ray.at(intersection.distance),
+                    dir: ray.direction,
+                    power,
+                })
+            } else {
+                None
+            };
+        }
+        let (child_ray, attenuation, pdf) = obj
+            .scatter(&ray, &intersection)
+            .filter(|scattered| scattered.2 > 0.)?;
+        power *= attenuation * obj.scattering_pdf(&ray, &intersection, &child_ray) / pdf;
+        specular = true;
+        ray = child_ray;
+    }
+    None
+}
+
+impl Integrator for CausticPhotons {
+    fn li(&self, world: &World, ray: &Ray, max_depth: usize) -> LobeColors {
+        let mut color = LobeColors::default();
+        let mut throughput = Color::ONE;
+        let mut ray = *ray;
+        let mut scatter_pdf = None;
+        let mut vertices = 0;
+        let mut first = None;
+        // Hitting a light after this through specular bounces is a caustic,
+        // which the photons already account for
+        let mut after_diffuse = false;
+        // Specular bounces since the last diffuse one
+        let mut specular_chain = 0;
+
+        for _ in 0..max_depth {
+            let (obj, intersection) = match world.first_intersection(ray, 0.00001, f32::INFINITY) {
+                Some(hit) => hit,
+                None => {
+                    let weight = match scatter_pdf {
+                        Some(pdf) => power_heuristic(pdf, world.background.pdf(ray.direction)),
+                        None => 1.,
+                    };
+                    color.add(
+                        Lobe::of(first, vertices),
+                        throughput * weight * world.background.value(ray.direction),
+                    );
+                    break;
+                }
+            };
+            vertices += 1;
+            // A photon is kept after at most `max_depth - 1` specular
+            // bounces, longer chains are left to the path tracer
+            let caustic = after_diffuse
+                && scatter_pdf.is_none()
+                && obj.light_pmf > 0.
+                && specular_chain < self.max_depth;
+            if !caustic {
+                let weight = match scatter_pdf {
+                    Some(pdf) if obj.light_pmf > 0. => {
+                        power_heuristic(pdf, world.emitter_pdf(&ray, &intersection, obj))
+                    }
+                    _ => 1.,
+                };
+                color.add(
+                    Lobe::of(first, vertices - 1),
+                    throughput * weight * obj.emit(&ray, &intersection),
+                );
+            }
+
+            let (child_ray, attenuation, pdf) = match obj.scatter(&ray, &intersection) {
+                Some(scattered) if scattered.2 > 0. => scattered,
+                _ => break,
+            };
+            if first.is_none() {
+                first = Some(first_event(obj, intersection.norm, &child_ray));
+            }
+            if obj.is_specular() {
+                scatter_pdf = None;
+                specular_chain += 1;
+            } else {
+                let direct = world.direct_light(&ray, &intersection, obj, attenuation, true);
+                color.add(Lobe::of(first, vertices), throughput * direct);
+                color.add(
+                    Lobe::of(first, vertices + 1),
+                    throughput * self.caustics(&ray, &intersection, obj),
+                );
+                scatter_pdf = Some(pdf);
+                after_diffuse = true;
+                specular_chain = 0;
+            }
+            throughput *= attenuation * obj.scattering_pdf(&ray, &intersection, &child_ray) / pdf;
+            ray = child_ray;
+        }
+
+        stats::count_path(vertices);
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::Pcg32;
+
+    #[test]
+    fn gather_matches_brute_force() {
+        let mut rng = Pcg32::new(9, 0);
+        let mut point = || Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32() * 0.2);
+        let photons: Vec<Photon> = (0..2000)
+            .map(|i| Photon {
+                p: point(),
+                dir: Vec3::Y,
+                // Tags the photon so the results can be compared
+                power: Color::splat(i as f32),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+
+        for _ in 0..50 {
+            let p = point();
+            for radius in [0.01, 0.05, 0.2] {
+                let mut found = vec![];
+                map.for_each_within(p, radius, |photon| found.push(photon.power.x as usize));
+                found.sort_unstable();
+                let expected: Vec<usize> = photons
+                    .iter()
+                    .filter(|photon| (photon.p - p).length_squared() < radius * radius)
+                    .map(|photon| photon.power.x as usize)
+                    .collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use glam::Vec3;
+use image::{codecs::png::PngEncoder, ColorType};
+
+use crate::{
+    adaptive::PixelStats,
+    camera::PerspectiveCamera,
+    color::RGB,
+    film::Film,
+    stats::ProgressTracker,
+    tile,
+    world::{RenderSettings, World},
+};
+
+/// Camera circling `lookat`, what the preview's mouse controls move around.
+/// `yaw` and `pitch` are in radians, with y up.
+#[derive(Clone, Copy)]
+pub struct Orbit {
+    pub lookat: Vec3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub vfov: f32,
+    pub aspect_ratio: f32,
+}
+
+impl Orbit {
+    pub fn new(origin: Vec3, lookat: Vec3, vfov: f32, aspect_ratio: f32) -> Self {
+        let offset = origin - lookat;
+        let distance = offset.length();
+        Self {
+            lookat,
+            distance,
+            yaw: offset.x.atan2(offset.z),
+            pitch: (offset.y / distance).asin(),
+            vfov,
+            aspect_ratio,
+        }
+    }
+
+    pub fn origin(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        self.lookat
+            + self.distance * Vec3::new(cos_pitch * sin_yaw, sin_pitch, cos_pitch * cos_yaw)
+    }
+
+    pub fn camera(&self) -> PerspectiveCamera {
+        PerspectiveCamera::new(
+            self.origin(),
+            self.lookat,
+            Vec3::Y,
+            self.vfov,
+            self.aspect_ratio,
+            0.,
+            self.distance,
+        )
+    }
+
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        let limit = 89f32.to_radians();
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).clamp(-limit, limit);
+    }
+
+    pub fn zoom(&mut self, factor: f32) {
+        self.distance = (self.distance * factor).max(1e-3);
+    }
+}
+
+struct Shared {
+    orbit: Orbit,
+    /// Bumped whenever the view changes so the renderer starts over
+    generation: u64,
+    frame: Vec<u8>,
+}
+
+/// Serves a page on `addr` (e.g. "127.0.0.1:8080") showing the render as it
+/// refines, one sample per pixel at a time up to `settings.samples_per_px`.
+/// Dragging on the image orbits the camera and the wheel zooms, either one
+/// throws away what was accumulated. Never returns.
+pub fn serve(world: &World, orbit: Orbit, settings: &RenderSettings, addr: &str) {
+    let listener = TcpListener::bind(addr).expect("Preview address to bind");
+    println!("Preview at http://{}", addr);
+
+    let shared = Arc::new(Mutex::new(Shared {
+        orbit,
+        generation: 0,
+        frame: vec![],
+    }));
+    {
+        let shared = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = handle(stream, &shared) {
+                    eprintln!("Preview request failed: {}", e);
+                }
+            }
+        });
+    }
+
+    let height = settings.height;
+    let width = (height as f32 * orbit.aspect_ratio) as usize;
+    let tiles = tile::tiles(width, height, settings.tile_size, settings.tile_order);
+    let mut pixels = vec![PixelStats::default(); width * height];
+    let mut film = Film::new(width, height);
+    let mut current = 0;
+
+    loop {
+        let (generation, camera) = {
+            let shared = shared.lock().unwrap();
+            (shared.generation, shared.orbit.camera())
+        };
+        if generation != current {
+            pixels = vec![PixelStats::default(); width * height];
+            film = Film::new(width, height);
+            current = generation;
+        }
+        if pixels[0].count >= settings.samples_per_px {
+            thread::sleep(Duration::from_millis(50));
+            continue;
+        }
+
+        let tracker = ProgressTracker::new(0);
+        world.sample_pixels(
+            &camera,
+            settings,
+            &tiles,
+            &mut pixels,
+            &mut film,
+            &mut [],
+            &tracker,
+            &|_| {},
+            |_| 1,
+        );
+
+        let mut shared = shared.lock().unwrap();
+        // Don't show a pass traced from a view that is already gone
+        if shared.generation == current {
+            shared.frame = encode(&film);
+        }
+    }
+}
+
+fn encode(film: &Film) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(film.width * film.height * 3);
+    for i in 0..film.width * film.height {
+        bytes.extend_from_slice(&film.color(i).to_px(1).0);
+    }
+    let mut png = vec![];
+    PngEncoder::new(&mut png)
+        .encode(&bytes, film.width as u32, film.height as u32, ColorType::Rgb8)
+        .expect("Frame to encode");
+    png
+}
+
+fn handle(mut stream: TcpStream, shared: &Mutex<Shared>) -> std::io::Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .find(|(k, _)| *k == name)
+            .and_then(|(_, v)| v.parse::<f32>().ok())
+    };
+
+    match path {
+        "/" => respond(&mut stream, "text/html", PAGE.as_bytes()),
+        "/frame.png" => {
+            let frame = shared.lock().unwrap().frame.clone();
+            respond(&mut stream, "image/png", &frame)
+        }
+        "/orbit" => {
+            let mut shared = shared.lock().unwrap();
+            shared
+                .orbit
+                .rotate(param("yaw").unwrap_or(0.), param("pitch").unwrap_or(0.));
+            shared.generation += 1;
+            respond(&mut stream, "text/plain", b"ok")
+        }
+        "/zoom" => {
+            let mut shared = shared.lock().unwrap();
+            shared.orbit.zoom(param("factor").unwrap_or(1.));
+            shared.generation += 1;
+            respond(&mut stream, "text/plain", b"ok")
+        }
+        _ => {
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")?;
+            Ok(())
+        }
+    }
+}
+
+fn respond(stream: &mut TcpStream, content_type: &str, body: &[u8]) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)
+}
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>Preview</title></head>
+<body style="margin:0;background:#222">
+<img id="frame" draggable="false" style="display:block;margin:auto">
+<script>
+const img = document.getElementById("frame");
+function refresh() {
+    const next = new Image();
+    next.onload = () => { img.src = next.src; setTimeout(refresh, 200); };
+    next.onerror = () => setTimeout(refresh, 500);
+    next.src = "/frame.png?" + Date.now();
+}
+refresh();
+let drag = null;
+img.addEventListener("mousedown", e => drag = [e.clientX, e.clientY]);
+window.addEventListener("mouseup", () => drag = null);
+window.addEventListener("mousemove", e => {
+    if (!drag) return;
+    const yaw = -(e.clientX - drag[0]) * 0.01;
+    const pitch = (e.clientY - drag[1]) * 0.01;
+    drag = [e.clientX, e.clientY];
+    fetch(`/orbit?yaw=${yaw}&pitch=${pitch}`);
+});
+img.addEventListener("wheel", e => {
+    e.preventDefault();
+    fetch(`/zoom?factor=${e.deltaY > 0 ? 1.1 : 1 / 1.1}`);
+});
+</script>
+</body>
+</html>
+"#;
@@ -0,0 +1,751 @@
+use std::{cell::RefCell, f32::consts::PI, rc::Rc, sync::OnceLock};
+
+/// Source of the random numbers used while tracing a sample. Every sample is
+/// started with the pixel and sample index it belongs to, so its numbers only
+/// depend on those and never on which thread happens to trace it.
+pub trait Sampler {
+    fn start_sample(&mut self, pixel: usize, index: usize);
+
+    /// Next value in `[0, 1)`.
+    fn next_1d(&mut self) -> f32;
+}
+
+/// PCG32 (XSH RR) from O'Neill, "PCG: A Family of Simple Fast Space-Efficient
+/// Statistically Good Algorithms for Random Number Generation".
+#[derive(Clone)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+/// SplitMix64 finalizer, spreads nearby inputs over the whole range.
+pub fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Independent uniform random numbers.
+pub struct RandomSampler {
+    seed: u64,
+    rng: Pcg32,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: Pcg32::new(mix(seed), 0),
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn start_sample(&mut self, pixel: usize, index: usize) {
+        self.rng = Pcg32::new(mix(self.seed ^ mix(pixel as u64)), index as u64);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+}
+
+/// Latin hypercube: every dimension is split into `samples_per_px` strata
+/// and each sample of a pixel lands in a different one, in a shuffled order
+/// per dimension. Samples past `samples_per_px`, which adaptive sampling
+/// takes, are independent random numbers.
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_px: usize,
+    pixel: u64,
+    index: usize,
+    dim: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_px: usize) -> Self {
+        Self {
+            seed,
+            samples_per_px: samples_per_px.max(1),
+            pixel: 0,
+            index: 0,
+            dim: 0,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, pixel: usize, index: usize) {
+        self.pixel = pixel as u64;
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let hash = mix(self.seed ^ mix(self.pixel ^ mix(self.dim)));
+        self.dim += 1;
+        let jitter = Pcg32::new(hash, self.index as u64).next_f32();
+        if self.index >= self.samples_per_px {
+            return jitter;
+        }
+        let n = self.samples_per_px as u32;
+        let stratum = permute(self.index as u32, n, hash as u32);
+        ((stratum as f32 + jitter) / n as f32).min(ONE_MINUS_EPSILON)
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Halton sequence with a random per pixel (Cranley-Patterson) offset, past
+/// the last prime base it falls back to independent random numbers.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    index: usize,
+    dim: usize,
+    rng: Pcg32,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dim: 0,
+            rng: Pcg32::new(mix(seed), 0),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, pixel: usize, index: usize) {
+        self.pixel = pixel as u64;
+        self.index = index;
+        self.dim = 0;
+        self.rng = Pcg32::new(mix(self.seed ^ mix(self.pixel)), index as u64);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dim = self.dim;
+        self.dim += 1;
+        if dim >= PRIMES.len() {
+            return self.rng.next_f32();
+        }
+        let offset = Pcg32::new(mix(self.seed ^ mix(self.pixel)), dim as u64).next_f32();
+        let x = radical_inverse(PRIMES[dim], self.index as u64) + offset;
+        (x - x.floor()).min(ONE_MINUS_EPSILON)
+    }
+}
+
+/// Halton sequence like `HaltonSampler`, but offset per pixel by a blue
+/// noise mask instead of at random, after Georgiev and Fajardo, "Blue-noise
+/// Dithered Sampling". Neighboring pixels get offsets far apart, so the
+/// noise left at low sample counts is fine grained instead of blotchy. Every
+/// dimension reads the mask at its own toroidal shift.
+pub struct BlueNoiseSampler {
+    seed: u64,
+    /// Of the image, to find where pixels fall on the mask
+    width: usize,
+    x: usize,
+    y: usize,
+    index: usize,
+    dim: usize,
+    rng: Pcg32,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64, width: usize) -> Self {
+        Self {
+            seed,
+            width: width.max(1),
+            x: 0,
+            y: 0,
+            index: 0,
+            dim: 0,
+            rng: Pcg32::new(mix(seed), 0),
+        }
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, pixel: usize, index: usize) {
+        self.x = pixel % self.width;
+        self.y = pixel / self.width;
+        self.index = index;
+        self.dim = 0;
+        self.rng = Pcg32::new(mix(self.seed ^ mix(pixel as u64)), index as u64);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dim = self.dim;
+        self.dim += 1;
+        if dim >= PRIMES.len() {
+            return self.rng.next_f32();
+        }
+        let shift = mix(self.seed ^ mix(dim as u64));
+        let size = BLUE_NOISE_SIZE as u64;
+        let x = (self.x + (shift % size) as usize) % BLUE_NOISE_SIZE;
+        let y = (self.y + ((shift >> 32) % size) as usize) % BLUE_NOISE_SIZE;
+        let offset = blue_noise_mask()[x + y * BLUE_NOISE_SIZE];
+        let x = radical_inverse(PRIMES[dim], self.index as u64) + offset;
+        (x - x.floor()).min(ONE_MINUS_EPSILON)
+    }
+}
+
+/// Side of the blue noise mask, which tiles the image
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Blue noise mask made with Ulichney's void and cluster method, "The
+/// void-and-cluster method for dither array generation". Every value in
+/// `(0, 1)` it holds appears once, and the pixels holding any range of
+/// values are spread out evenly.
+fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let size = BLUE_NOISE_SIZE;
+        let n = size * size;
+        // Toroidal Gaussian falloff by offset, with the paper's sigma of 1.5
+        let kernel: Vec<f32> = (0..n)
+            .map(|i| {
+                let d = |c: usize| c.min(size - c) as f32;
+                let (dx, dy) = (d(i % size), d(i / size));
+                (-(dx * dx + dy * dy) / (2. * 1.5 * 1.5)).exp()
+            })
+            .collect();
+        // Flips pixel `i` of the pattern, keeping `energy`, how crowded the
+        // points around every pixel are, up to date
+        let toggle = |ones: &mut [bool], energy: &mut [f32], i: usize| {
+            ones[i] = !ones[i];
+            let sign = if ones[i] { 1. } else { -1. };
+            let (x, y) = (i % size, i / size);
+            for (j, e) in energy.iter_mut().enumerate() {
+                let dx = (j % size + size - x) % size;
+                let dy = (j / size + size - y) % size;
+                *e += sign * kernel[dx + dy * size];
+            }
+        };
+        let tightest_cluster = |ones: &[bool], energy: &[f32]| {
+            (0..n)
+                .filter(|&i| ones[i])
+                .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+        let largest_void = |ones: &[bool], energy: &[f32]| {
+            (0..n)
+                .filter(|&i| !ones[i])
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+
+        // Random initial points, moved from the tightest cluster to the
+        // largest void until that puts them back where they were
+        let mut ones = vec![false; n];
+        let mut energy = vec![0.; n];
+        let mut rng = Pcg32::new(0x9e3779b97f4a7c15, 0);
+        let initial = n / 10;
+        let mut placed = 0;
+        while placed < initial {
+            let i = rng.next_u32() as usize % n;
+            if !ones[i] {
+                toggle(&mut ones, &mut energy, i);
+                placed += 1;
+            }
+        }
+        loop {
+            let cluster = tightest_cluster(&ones, &energy);
+            toggle(&mut ones, &mut energy, cluster);
+            let void = largest_void(&ones, &energy);
+            toggle(&mut ones, &mut energy, void);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // The initial points are ranked by taking them away tightest cluster
+        // first, the other pixels by filling the largest void first
+        let mut rank = vec![0; n];
+        let (mut fewer, mut fewer_energy) = (ones.clone(), energy.clone());
+        for r in (0..initial).rev() {
+            let cluster = tightest_cluster(&fewer, &fewer_energy);
+            toggle(&mut fewer, &mut fewer_energy, cluster);
+            rank[cluster] = r;
+        }
+        for r in initial..n {
+            let void = largest_void(&ones, &energy);
+            toggle(&mut ones, &mut energy, void);
+            rank[void] = r;
+        }
+        rank.iter().map(|&r| (r as f32 + 0.5) / n as f32).collect()
+    })
+}
+
+/// Sobol sequence with hash based Owen scrambling after Burley, "Practical
+/// Hash-based Owen Scrambling". Dimensions come in groups of four, each
+/// group being its own independently scrambled and shuffled 4D Sobol set.
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dim: u32,
+    directions: [[u32; 32]; 4],
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        // (degree, coefficients, initial direction numbers) of the primitive
+        // polynomials for dimensions 2 to 4, from Joe and Kuo
+        let polys: [(usize, u32, [u32; 3]); 3] =
+            [(1, 0, [1, 0, 0]), (2, 1, [1, 3, 0]), (3, 1, [1, 3, 1])];
+
+        let mut directions = [[0; 32]; 4];
+        for (k, v) in directions[0].iter_mut().enumerate() {
+            *v = 1 << (31 - k);
+        }
+        for (d, &(s, a, m)) in polys.iter().enumerate() {
+            let v = &mut directions[d + 1];
+            for k in 0..32 {
+                v[k] = if k < s {
+                    m[k] << (31 - k)
+                } else {
+                    let mut x = v[k - s] ^ (v[k - s] >> s);
+                    for j in 1..s {
+                        if (a >> (s - 1 - j)) & 1 == 1 {
+                            x ^= v[k - j];
+                        }
+                    }
+                    x
+                };
+            }
+        }
+
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dim: 0,
+            directions,
+        }
+    }
+
+    fn sobol(&self, index: u32, dim: usize) -> u32 {
+        let mut x = 0;
+        let mut i = index;
+        let mut k = 0;
+        while i != 0 {
+            if i & 1 == 1 {
+                x ^= self.directions[dim][k];
+            }
+            i >>= 1;
+            k += 1;
+        }
+        x
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, pixel: usize, index: usize) {
+        self.pixel = pixel as u64;
+        self.index = index as u32;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let group = (self.dim / 4) as u64;
+        let dim = (self.dim % 4) as usize;
+        self.dim += 1;
+
+        let group_seed = mix(self.seed ^ mix(self.pixel ^ mix(group)));
+        let index = nested_uniform_scramble(self.index, group_seed as u32);
+        let x = nested_uniform_scramble(self.sobol(index, dim), mix(group_seed ^ dim as u64) as u32);
+        (x as f32 / 4294967296.).min(ONE_MINUS_EPSILON)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct PrimarySample {
+    value: f32,
+    /// Iteration the value was last changed in
+    modified: u64,
+    backup: f32,
+    backup_modified: u64,
+}
+
+/// Primary sample space sampler for Metropolis light transport, after
+/// Kelemen et al., "A Simple and Robust Mutation Strategy for the Metropolis
+/// Light Transport Algorithm", laid out like pbrt's.
+///
+/// Every sample replays the same vector of numbers, so a path is fully
+/// described by it. Each iteration mutates that vector, either slightly or
+/// by drawing it afresh, and a rejected mutation is undone. Numbers are only
+/// mutated once they are asked for, catching up on the small steps they
+/// missed in one go.
+pub struct MltSampler {
+    rng: Pcg32,
+    sigma: f32,
+    large_step_probability: f32,
+    samples: Vec<PrimarySample>,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    index: usize,
+}
+
+impl MltSampler {
+    pub fn new(seed: u64, sigma: f32, large_step_probability: f32) -> Self {
+        Self {
+            rng: Pcg32::new(mix(seed), 0),
+            sigma,
+            large_step_probability,
+            samples: vec![],
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0,
+        }
+    }
+
+    /// Starts proposing a mutation of the current numbers.
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.next_f32() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Goes back to the numbers from before the iteration.
+    pub fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    fn ensure_ready(&mut self, index: usize) {
+        if index >= self.samples.len() {
+            self.samples.resize(index + 1, PrimarySample::default());
+        }
+        let sample = &mut self.samples[index];
+        // Numbers untouched since the last accepted large step would have
+        // been redrawn by it
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.next_f32();
+            sample.modified = self.last_large_step;
+        }
+
+        sample.backup = sample.value;
+        sample.backup_modified = sample.modified;
+        if self.large_step {
+            sample.value = self.rng.next_f32();
+        } else {
+            let steps = (self.iteration - sample.modified) as f32;
+            // Box-Muller, the sum of `steps` normal steps is one wider one
+            let r = (-2. * (1. - self.rng.next_f32()).ln()).sqrt();
+            let normal = r * (2. * PI * self.rng.next_f32()).cos();
+            sample.value += normal * self.sigma * steps.sqrt();
+            sample.value = (sample.value - sample.value.floor()).min(ONE_MINUS_EPSILON);
+        }
+        sample.modified = self.iteration;
+    }
+}
+
+impl Sampler for MltSampler {
+    /// Replays the current numbers from the start.
+    fn start_sample(&mut self, _pixel: usize, _index: usize) {
+        self.index = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let index = self.index;
+        self.index += 1;
+        self.ensure_ready(index);
+        self.samples[index].value
+    }
+}
+
+/// Lets the caller keep a handle on the sampler `random()` draws from, to
+/// steer it between samples.
+impl<S: Sampler> Sampler for Rc<RefCell<S>> {
+    fn start_sample(&mut self, pixel: usize, index: usize) {
+        self.borrow_mut().start_sample(pixel, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.borrow_mut().next_1d()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SamplerKind {
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    /// Sampler for an image `width` pixels wide, with pixels numbered row
+    /// by row.
+    pub fn build(&self, seed: u64, samples_per_px: usize, width: usize) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Random => Box::new(RandomSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_px)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed, width)),
+        }
+    }
+}
+
+const ONE_MINUS_EPSILON: f32 = 1. - f32::EPSILON / 2.;
+
+fn radical_inverse(base: u32, mut index: u64) -> f32 {
+    let base = base as u64;
+    let inv_base = 1. / base as f64;
+    let mut reversed = 0;
+    let mut inv_base_n = 1.;
+    while index > 0 {
+        let next = index / base;
+        reversed = reversed * base + (index - next * base);
+        inv_base_n *= inv_base;
+        index = next;
+    }
+    (reversed as f64 * inv_base_n) as f32
+}
+
+/// Kensler's hash based permutation of `[0, l)`, picks where `i` goes in
+/// the permutation selected by `p`.
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    if l <= 1 {
+        return 0;
+    }
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+thread_local! {
+    static SAMPLER: RefCell<Box<dyn Sampler>> = RefCell::new(Box::new(RandomSampler::new(0)));
+}
+
+/// Replaces the sampler `random()` draws from on this thread.
+pub fn set_sampler(sampler: Box<dyn Sampler>) {
+    SAMPLER.with(|s| *s.borrow_mut() = sampler);
+}
+
+pub fn start_sample(pixel: usize, index: usize) {
+    SAMPLER.with(|s| s.borrow_mut().start_sample(pixel, index));
+}
+
+pub fn next_1d() -> f32 {
+    SAMPLER.with(|s| s.borrow_mut().next_1d())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first `n` samples of `pixel`, each `dims` numbers long.
+    fn draw(sampler: &mut impl Sampler, pixel: usize, n: usize, dims: usize) -> Vec<Vec<f32>> {
+        (0..n)
+            .map(|i| {
+                sampler.start_sample(pixel, i);
+                (0..dims).map(|_| sampler.next_1d()).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stratified_covers_every_stratum_once() {
+        let n = 16;
+        let mut sampler = StratifiedSampler::new(7, n);
+        let points = draw(&mut sampler, 5, 2 * n, 4);
+        for dim in 0..4 {
+            let mut strata = vec![false; n];
+            for point in &points[..n] {
+                let stratum = (point[dim] * n as f32) as usize;
+                assert!(!strata[stratum], "dimension {} stratum {}", dim, stratum);
+                strata[stratum] = true;
+            }
+            // Past samples_per_px there is nothing left to stratify
+            for point in &points[n..] {
+                assert!((0. ..1.).contains(&point[dim]));
+            }
+        }
+    }
+
+    #[test]
+    fn blue_noise_mask_holds_every_rank_once_spread_out() {
+        let mask = blue_noise_mask();
+        let n = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        let mut ranks: Vec<usize> = mask.iter().map(|v| (v * n as f32) as usize).collect();
+        ranks.sort_unstable();
+        assert!(ranks.iter().copied().eq(0..n));
+
+        // Unlike white noise, the darkest sixteenth never has two pixels
+        // next to each other
+        let dark = |x: usize, y: usize| {
+            mask[x % BLUE_NOISE_SIZE + (y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE] < 1. / 16.
+        };
+        for y in 0..BLUE_NOISE_SIZE {
+            for x in 0..BLUE_NOISE_SIZE {
+                if dark(x, y) {
+                    assert!(!dark(x + 1, y) && !dark(x, y + 1), "({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_is_in_unit_interval_and_stratified() {
+        let n = 256;
+        let mut sampler = SobolSampler::new(3);
+        for pixel in [0, 17] {
+            let points = draw(&mut sampler, pixel, n, 8);
+            for dim in 0..8 {
+                let mut strata = vec![false; n];
+                for point in &points {
+                    let x = point[dim];
+                    assert!((0. ..1.).contains(&x));
+                    let stratum = (x * n as f32) as usize;
+                    assert!(!strata[stratum], "dimension {} stratum {}", dim, stratum);
+                    strata[stratum] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_pairs_are_stratified_in_every_elementary_interval() {
+        let n = 16;
+        let mut sampler = SobolSampler::new(5);
+        let points = draw(&mut sampler, 2, n, 2);
+        // Splitting the square into 2^a by 2^(4 - a) cells, every cell gets
+        // exactly one point
+        for a in 0..=4 {
+            let (nx, ny) = (1 << a, 1 << (4 - a));
+            let mut cells = vec![false; n];
+            for point in &points {
+                let cell = (point[0] * nx as f32) as usize + (point[1] * ny as f32) as usize * nx;
+                assert!(!cells[cell], "{} by {} cell {}", nx, ny, cell);
+                cells[cell] = true;
+            }
+        }
+    }
+
+    fn current(sampler: &MltSampler) -> Vec<f32> {
+        sampler.samples.iter().map(|sample| sample.value).collect()
+    }
+
+    #[test]
+    fn mlt_sampler_restores_numbers_on_reject() {
+        let mut sampler = MltSampler::new(11, 0.01, 0.3);
+        let accepted = draw(&mut sampler, 0, 1, 6).remove(0);
+        for _ in 0..20 {
+            sampler.start_iteration();
+            let proposed = draw(&mut sampler, 0, 1, 6).remove(0);
+            assert_ne!(proposed, accepted);
+            sampler.reject();
+            assert_eq!(current(&sampler), accepted);
+        }
+    }
+
+    #[test]
+    fn mlt_sampler_is_deterministic_per_seed() {
+        let run = |seed| {
+            let mut sampler = MltSampler::new(seed, 0.01, 0.3);
+            let mut numbers = draw(&mut sampler, 0, 1, 6);
+            for i in 0..20 {
+                sampler.start_iteration();
+                numbers.extend(draw(&mut sampler, 0, 1, 6));
+                if i % 3 == 0 {
+                    sampler.reject();
+                } else {
+                    sampler.accept();
+                }
+            }
+            numbers
+        };
+        assert_eq!(run(4), run(4));
+        assert_ne!(run(4), run(5));
+    }
+}
@@ -0,0 +1,215 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::{
+    background::Background,
+    color::{xyz_to_rgb, Color},
+    orthonormalbasis::OrthoNormalBasis,
+    random,
+};
+
+/// Preetham et al. "A Practical Analytic Model for Daylight" sky with a sun
+/// disk. Below the horizon the sky is the constant `ground` color.
+pub struct Sky {
+    pub sun_dir: Vec3,
+    pub turbidity: f32,
+    /// Scales the sky from the model's kcd/m^2 into scene radiance
+    pub intensity: f32,
+    /// Irradiance the sun delivers to a surface facing it, before the
+    /// atmosphere attenuates it
+    pub sun_intensity: f32,
+    /// Angular radius of the sun disk in radians
+    pub sun_radius: f32,
+    pub ground: Color,
+    /// Zenith luminance and chromaticity as (Y, x, y)
+    zenith: Vec3,
+    /// Perez coefficients A..E for Y, x and y
+    perez: [[f32; 5]; 3],
+    sun_transmittance: Color,
+}
+
+impl Sky {
+    pub fn new(sun_dir: Vec3, turbidity: f32) -> Self {
+        let sun_dir = sun_dir.normalize();
+        let t = turbidity;
+        let theta_s = sun_dir.y.clamp(-1., 1.).acos().min(PI / 2.);
+
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let zenith_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.);
+
+        let ts = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.];
+        let tt = [t * t, t, 1.];
+        let chroma = |m: [[f32; 4]; 3]| -> f32 {
+            (0..3)
+                .map(|i| tt[i] * (0..4).map(|j| m[i][j] * ts[j]).sum::<f32>())
+                .sum()
+        };
+        let zenith_x = chroma([
+            [0.00166, -0.00375, 0.00209, 0.],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_yc = chroma([
+            [0.00275, -0.00610, 0.00317, 0.],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        Self {
+            sun_dir,
+            turbidity,
+            intensity: 0.05,
+            sun_intensity: 3.,
+            sun_radius: 0.00465,
+            ground: Color::splat(0.05),
+            zenith: Vec3::new(zenith_y, zenith_x, zenith_yc),
+            perez,
+            sun_transmittance: sun_transmittance(theta_s, turbidity),
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_sun_intensity(mut self, sun_intensity: f32) -> Self {
+        self.sun_intensity = sun_intensity;
+        self
+    }
+
+    pub fn with_ground(mut self, ground: Color) -> Self {
+        self.ground = ground;
+        self
+    }
+
+    fn perez(coeffs: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = *coeffs;
+        (1. + a * (b / cos_theta).exp()) * (1. + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+
+    fn sky(&self, dir: Vec3) -> Color {
+        let cos_theta = dir.y.max(0.001);
+        let gamma = dir.dot(self.sun_dir).clamp(-1., 1.).acos();
+        let theta_s = self.sun_dir.y.clamp(-1., 1.).acos();
+
+        let mut xyy = [0.; 3];
+        for i in 0..3 {
+            xyy[i] = self.zenith[i] * Self::perez(&self.perez[i], cos_theta, gamma)
+                / Self::perez(&self.perez[i], 1., theta_s);
+        }
+        let [lum, x, y] = xyy;
+        if y <= 0. {
+            return Color::ZERO;
+        }
+        let xyz = Vec3::new(x / y * lum, lum, (1. - x - y) / y * lum);
+        xyz_to_rgb(xyz).max(Color::ZERO) * self.intensity
+    }
+
+    fn cos_sun_radius(&self) -> f32 {
+        self.sun_radius.cos()
+    }
+
+    fn sun_radiance(&self) -> Color {
+        let solid_angle = 2. * PI * (1. - self.cos_sun_radius());
+        self.sun_transmittance * self.sun_intensity / solid_angle
+    }
+
+    /// Chance of sending a shadow ray at the sun rather than at the sky.
+    fn sun_prob(&self) -> f32 {
+        if self.sun_dir.y > 0. && self.sun_intensity > 0. {
+            0.5
+        } else {
+            0.
+        }
+    }
+}
+
+impl Background for Sky {
+    fn value(&self, dir: Vec3) -> Color {
+        let dir = dir.normalize();
+        if dir.y <= 0. {
+            return self.ground;
+        }
+        let mut color = self.sky(dir);
+        if self.sun_prob() > 0. && dir.dot(self.sun_dir) >= self.cos_sun_radius() {
+            color += self.sun_radiance();
+        }
+        color
+    }
+
+    fn sample(&self) -> Option<(Vec3, f32)> {
+        let dir = if random() < self.sun_prob() {
+            let cos_max = self.cos_sun_radius();
+            let cos_theta = 1. - random() * (1. - cos_max);
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+            let phi = 2. * PI * random();
+            let uvw = OrthoNormalBasis::from_w(&self.sun_dir);
+            uvw.local(&Vec3::new(
+                phi.cos() * sin_theta,
+                phi.sin() * sin_theta,
+                cos_theta,
+            ))
+        } else {
+            // Uniform over the sphere
+            let z = 1. - 2. * random();
+            let r = (1. - z * z).sqrt();
+            let phi = 2. * PI * random();
+            Vec3::new(r * phi.cos(), r * phi.sin(), z)
+        };
+        Some((dir, self.pdf(dir)))
+    }
+
+    fn pdf(&self, dir: Vec3) -> f32 {
+        let sun_prob = self.sun_prob();
+        let cos_max = self.cos_sun_radius();
+        let sun_pdf = if dir.normalize().dot(self.sun_dir) >= cos_max {
+            1. / (2. * PI * (1. - cos_max))
+        } else {
+            0.
+        };
+        sun_prob * sun_pdf + (1. - sun_prob) / (4. * PI)
+    }
+}
+
+/// Attenuation of direct sunlight by Rayleigh and aerosol scattering along
+/// the air mass in the sun's direction, evaluated at 680, 550 and 440nm.
+fn sun_transmittance(theta_s: f32, turbidity: f32) -> Color {
+    let lambda = Vec3::new(0.68, 0.55, 0.44);
+    let zenith_deg = theta_s.to_degrees();
+    let air_mass = 1. / (theta_s.cos() + 0.15 * (93.885 - zenith_deg).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    let mut transmittance = Color::ZERO;
+    for i in 0..3 {
+        let rayleigh = 0.008735 * lambda[i].powf(-4.08);
+        let aerosol = beta * lambda[i].powf(-1.3);
+        transmittance[i] = (-(rayleigh + aerosol) * air_mass).exp();
+    }
+    transmittance
+}
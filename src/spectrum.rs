@@ -0,0 +1,269 @@
+use std::cell::Cell;
+
+use bvh::ray::Ray;
+use glam::{Vec3, Vec4};
+
+use crate::{
+    color::Color,
+    integrator::{first_event, Integrator},
+    lpe::{Lobe, LobeColors},
+    material::Material,
+    power_heuristic, random, stats,
+    world::World,
+};
+
+/// Visible range the wavelengths are sampled from, in nanometers.
+pub const LAMBDA_MIN: f32 = 360.;
+pub const LAMBDA_MAX: f32 = 830.;
+
+thread_local! {
+    static WAVELENGTH: Cell<Option<f32>> = Cell::new(None);
+}
+
+/// Sets the wavelength dispersive materials scatter for on this thread, None
+/// when rendering in RGB.
+pub fn set_wavelength(wavelength: Option<f32>) {
+    WAVELENGTH.with(|w| w.set(wavelength));
+}
+
+pub fn wavelength() -> Option<f32> {
+    WAVELENGTH.with(|w| w.get())
+}
+
+/// Hero wavelength sampling after Wilkie et al., "Hero Wavelength Spectral
+/// Sampling": a uniformly sampled wavelength and three more spaced evenly
+/// across the range from it, wrapping around.
+pub fn sample_wavelengths(u: f32) -> Vec4 {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let hero = u * range;
+    let wrap = |offset: f32| LAMBDA_MIN + (hero + offset * range / 4.) % range;
+    Vec4::new(wrap(0.), wrap(1.), wrap(2.), wrap(3.))
+}
+
+/// Basis spectra from Smits, "An RGB-to-Spectrum Conversion for
+/// Reflectances", in 10 bins across 380 to 720 nm.
+const SMITS_WHITE: [f32; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f32; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f32; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f32; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f32; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Linear interpolation between the bin centers, held flat past the ends.
+fn smits(basis: &[f32; 10], wavelength: f32) -> f32 {
+    let bin = 34.;
+    let x = ((wavelength - 380.) / bin - 0.5).clamp(0., 9.);
+    let i = (x as usize).min(8);
+    let t = x - i as f32;
+    basis[i] * (1. - t) + basis[i + 1] * t
+}
+
+/// A smooth spectrum that looks like `rgb`, evaluated at `wavelengths`.
+/// Meant for reflectances but good enough for light as well.
+pub fn uplift(rgb: Color, wavelengths: Vec4) -> Vec4 {
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    // White covers the smallest component, the secondary color the middle
+    // one and the primary what's left of the largest
+    let terms: [(f32, &[f32; 10]); 3] = if r <= g && r <= b {
+        if g <= b {
+            [
+                (r, &SMITS_WHITE),
+                (g - r, &SMITS_CYAN),
+                (b - g, &SMITS_BLUE),
+            ]
+        } else {
+            [
+                (r, &SMITS_WHITE),
+                (b - r, &SMITS_CYAN),
+                (g - b, &SMITS_GREEN),
+            ]
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            [
+                (g, &SMITS_WHITE),
+                (r - g, &SMITS_MAGENTA),
+                (b - r, &SMITS_BLUE),
+            ]
+        } else {
+            [
+                (g, &SMITS_WHITE),
+                (b - g, &SMITS_MAGENTA),
+                (r - b, &SMITS_RED),
+            ]
+        }
+    } else if r <= g {
+        [
+            (b, &SMITS_WHITE),
+            (r - b, &SMITS_YELLOW),
+            (g - r, &SMITS_GREEN),
+        ]
+    } else {
+        [
+            (b, &SMITS_WHITE),
+            (g - b, &SMITS_YELLOW),
+            (r - g, &SMITS_RED),
+        ]
+    };
+
+    let at = |wavelength: f32| {
+        terms
+            .iter()
+            .map(|(weight, basis)| weight * smits(basis, wavelength))
+            .sum::<f32>()
+            .max(0.)
+    };
+    Vec4::new(
+        at(wavelengths.x),
+        at(wavelengths.y),
+        at(wavelengths.z),
+        at(wavelengths.w),
+    )
+}
+
+/// Piecewise Gaussian with different widths either side of `mean`.
+fn lobe(x: f32, mean: f32, sigma_below: f32, sigma_above: f32) -> f32 {
+    let t = (x - mean) / if x < mean { sigma_below } else { sigma_above };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 color matching functions, using the multi-lobe fits from Wyman
+/// et al., "Simple Analytic Approximations to the CIE XYZ Color Matching
+/// Functions".
+fn cie_xyz(wavelength: f32) -> Vec3 {
+    let l = wavelength;
+    Vec3::new(
+        1.056 * lobe(l, 599.8, 37.9, 31.0) + 0.362 * lobe(l, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(l, 501.1, 20.4, 26.2),
+        0.821 * lobe(l, 568.8, 46.9, 40.5) + 0.286 * lobe(l, 530.9, 16.3, 31.1),
+        1.217 * lobe(l, 437.0, 11.8, 36.0) + 0.681 * lobe(l, 459.0, 26.0, 13.8),
+    )
+}
+
+/// Integral of the Y matching function, so a constant spectrum of one comes
+/// out with a luminance of one.
+const CIE_Y_INTEGRAL: f32 = 106.856895;
+
+fn xyz_to_linear_srgb(xyz: Vec3) -> Color {
+    Color::new(
+        3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+        -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+        0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+    )
+}
+
+/// Estimates the RGB color of light with `values` at the sampled
+/// `wavelengths`. The equal energy white that a white `uplift` gives is
+/// balanced to RGB white, so scenes keep their colors in spectral mode.
+pub fn to_rgb(values: Vec4, wavelengths: Vec4) -> Color {
+    let xyz = (0..4)
+        .map(|i| cie_xyz(wavelengths[i]) * values[i])
+        .sum::<Vec3>()
+        * (LAMBDA_MAX - LAMBDA_MIN)
+        / (4. * CIE_Y_INTEGRAL);
+    xyz_to_linear_srgb(xyz) / xyz_to_linear_srgb(Vec3::ONE)
+}
+
+/// `PathTracer` carrying four wavelengths instead of RGB. Material, texture,
+/// light and background colors are uplifted to spectra as they are met, and
+/// `Dielectric`s with a `Dispersion` bend every wavelength by its own amount,
+/// so glass splits white light into a rainbow. Past such a surface only the
+/// first wavelength carries on, which is noisy, so dispersive scenes need
+/// plenty of samples.
+pub struct SpectralPathTracer;
+
+impl Integrator for SpectralPathTracer {
+    fn li(&self, world: &World, ray: &Ray, max_depth: usize) -> LobeColors {
+        let wavelengths = sample_wavelengths(random());
+        set_wavelength(Some(wavelengths.x));
+
+        let mut color = LobeColors::default();
+        let mut throughput = Vec4::ONE;
+        let mut ray = *ray;
+        let mut scatter_pdf = None;
+        let mut vertices = 0;
+        let mut first = None;
+        let mut single_wavelength = false;
+
+        for _ in 0..max_depth {
+            let (obj, intersection) = match world.first_intersection(ray, 0.00001, f32::INFINITY) {
+                Some(hit) => hit,
+                None => {
+                    let weight = match scatter_pdf {
+                        Some(pdf) => power_heuristic(pdf, world.background.pdf(ray.direction)),
+                        None => 1.,
+                    };
+                    let background = uplift(world.background.value(ray.direction), wavelengths);
+                    color.add(
+                        Lobe::of(first, vertices),
+                        to_rgb(throughput * background * weight, wavelengths),
+                    );
+                    break;
+                }
+            };
+            vertices += 1;
+            let weight = match scatter_pdf {
+                Some(pdf) if obj.light_pmf > 0. => {
+                    power_heuristic(pdf, world.emitter_pdf(&ray, &intersection, obj))
+                }
+                _ => 1.,
+            };
+            let emit = uplift(obj.emit(&ray, &intersection), wavelengths);
+            color.add(
+                Lobe::of(first, vertices - 1),
+                to_rgb(throughput * emit * weight, wavelengths),
+            );
+
+            let (child_ray, attenuation, pdf) = match obj.scatter(&ray, &intersection) {
+                Some(scattered) if scattered.2 > 0. => scattered,
+                _ => break,
+            };
+            if first.is_none() {
+                first = Some(first_event(obj, intersection.norm, &child_ray));
+            }
+            let attenuation = uplift(attenuation, wavelengths);
+            if obj.is_specular() {
+                scatter_pdf = None;
+            } else {
+                // Lights are uplifted on their own, the surface color is
+                // applied per wavelength
+                let light = world.direct_light(&ray, &intersection, obj, Color::ONE, true);
+                color.add(
+                    Lobe::of(first, vertices),
+                    to_rgb(
+                        throughput * attenuation * uplift(light, wavelengths),
+                        wavelengths,
+                    ),
+                );
+                scatter_pdf = Some(pdf);
+            }
+            throughput *= attenuation * obj.scattering_pdf(&ray, &intersection, &child_ray) / pdf;
+            if obj.is_dispersive() && !single_wavelength {
+                // The other wavelengths would have gone elsewhere, the hero
+                // stands in for all four
+                throughput *= Vec4::new(4., 0., 0., 0.);
+                single_wavelength = true;
+            }
+            ray = child_ray;
+        }
+
+        set_wavelength(None);
+        stats::count_path(vertices);
+        color
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    cell::Cell,
+    io::Write,
+    ops::AddAssign,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Counters gathered while tracing. They are kept per thread so tracing
+/// never has to synchronize, and collected once a tile is done.
+#[derive(Clone, Copy, Default)]
+pub struct RenderStats {
+    /// Camera rays and the rays they scattered into
+    pub rays: u64,
+    pub shadow_rays: u64,
+    /// Objects intersected, the ones whose bounds the BVH traversal let
+    /// through
+    pub objects_tested: u64,
+    /// Nodes visited walking the scene's BVH and those of the meshes in it
+    pub bvh_nodes_visited: u64,
+    pub paths: u64,
+    /// Surfaces hit along all paths
+    pub path_vertices: u64,
+}
+
+impl RenderStats {
+    pub fn average_path_length(&self) -> f32 {
+        if self.paths == 0 {
+            return 0.;
+        }
+        self.path_vertices as f32 / self.paths as f32
+    }
+
+    pub fn objects_tested_per_ray(&self) -> f32 {
+        let rays = self.rays + self.shadow_rays;
+        if rays == 0 {
+            return 0.;
+        }
+        self.objects_tested as f32 / rays as f32
+    }
+
+    pub fn bvh_nodes_visited_per_ray(&self) -> f32 {
+        let rays = self.rays + self.shadow_rays;
+        if rays == 0 {
+            return 0.;
+        }
+        self.bvh_nodes_visited as f32 / rays as f32
+    }
+}
+
+impl AddAssign for RenderStats {
+    fn add_assign(&mut self, other: Self) {
+        self.rays += other.rays;
+        self.shadow_rays += other.shadow_rays;
+        self.objects_tested += other.objects_tested;
+        self.bvh_nodes_visited += other.bvh_nodes_visited;
+        self.paths += other.paths;
+        self.path_vertices += other.path_vertices;
+    }
+}
+
+thread_local! {
+    static LOCAL: Cell<RenderStats> = Cell::new(RenderStats::default());
+}
+
+fn update(f: impl FnOnce(&mut RenderStats)) {
+    LOCAL.with(|local| {
+        let mut stats = local.get();
+        f(&mut stats);
+        local.set(stats);
+    });
+}
+
+pub fn count_ray() {
+    update(|s| s.rays += 1);
+}
+
+pub fn count_shadow_ray() {
+    update(|s| s.shadow_rays += 1);
+}
+
+pub fn count_object_test() {
+    update(|s| s.objects_tested += 1);
+}
+
+pub fn count_bvh_nodes(nodes: usize) {
+    update(|s| s.bvh_nodes_visited += nodes as u64);
+}
+
+pub fn count_path(vertices: usize) {
+    update(|s| {
+        s.paths += 1;
+        s.path_vertices += vertices as u64;
+    });
+}
+
+/// Returns this thread's counters, leaving them as they are.
+pub fn peek() -> RenderStats {
+    LOCAL.with(|local| local.get())
+}
+
+/// Returns this thread's counters and resets them.
+pub fn take() -> RenderStats {
+    LOCAL.with(|local| local.replace(RenderStats::default()))
+}
+
+/// Snapshot of a render in flight, handed to the progress callback every
+/// time a tile finishes.
+#[derive(Clone, Copy)]
+pub struct Progress {
+    /// Which pass over the tiles this is, adaptive renders take several
+    pub pass: usize,
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    pub samples_done: usize,
+    /// Samples the whole render is budgeted for
+    pub samples_total: usize,
+    pub elapsed: Duration,
+    pub stats: RenderStats,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f32 {
+        if self.samples_total == 0 {
+            return 1.;
+        }
+        (self.samples_done as f32 / self.samples_total as f32).min(1.)
+    }
+
+    pub fn rays_per_sec(&self) -> f32 {
+        let secs = self.elapsed.as_secs_f32();
+        if secs <= 0. {
+            return 0.;
+        }
+        (self.stats.rays + self.stats.shadow_rays) as f32 / secs
+    }
+
+    /// Time left assuming the rest goes as fast as what's done so far.
+    /// Adaptive renders may stop early, so for them this is an upper bound.
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction();
+        if fraction <= 0. {
+            return None;
+        }
+        Some(self.elapsed.mul_f32((1. - fraction) / fraction))
+    }
+}
+
+/// Shared between the threads of a render to put `Progress` together.
+pub struct ProgressTracker {
+    start: Instant,
+    samples_total: usize,
+    state: Mutex<Progress>,
+}
+
+impl ProgressTracker {
+    pub fn new(samples_total: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            samples_total,
+            state: Mutex::new(Progress {
+                pass: 0,
+                tiles_done: 0,
+                tiles_total: 0,
+                samples_done: 0,
+                samples_total,
+                elapsed: Duration::ZERO,
+                stats: RenderStats::default(),
+            }),
+        }
+    }
+
+    pub fn start_pass(&self, tiles_total: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.pass += 1;
+        state.tiles_done = 0;
+        state.tiles_total = tiles_total;
+    }
+
+    /// Records a finished tile and returns the updated snapshot.
+    pub fn tile_done(&self, samples: usize, stats: RenderStats) -> Progress {
+        let mut state = self.state.lock().unwrap();
+        state.tiles_done += 1;
+        state.samples_done += samples;
+        state.samples_total = self.samples_total;
+        state.elapsed = self.start.elapsed();
+        state.stats += stats;
+        *state
+    }
+
+    pub fn finish(&self) -> Progress {
+        let mut state = self.state.lock().unwrap();
+        state.elapsed = self.start.elapsed();
+        *state
+    }
+}
+
+/// Progress callback for the command line, keeps rewriting a single line.
+pub fn print_progress(progress: &Progress) {
+    let eta = progress
+        .eta()
+        .map(|eta| format!("{}s", eta.as_secs()))
+        .unwrap_or_else(|| "?".to_string());
+    print!(
+        "\rpass {} tile {}/{}  {:5.1}%  {:.2} Mrays/s  ETA {}   ",
+        progress.pass,
+        progress.tiles_done,
+        progress.tiles_total,
+        progress.fraction() * 100.,
+        progress.rays_per_sec() / 1e6,
+        eta
+    );
+    std::io::stdout().flush().ok();
+}
+
+pub fn report(progress: &Progress) {
+    let stats = &progress.stats;
+    println!();
+    println!("Rays cast: {} (+{} shadow rays)", stats.rays, stats.shadow_rays);
+    println!(
+        "Objects tested: {} ({:.2} per ray)",
+        stats.objects_tested,
+        stats.objects_tested_per_ray()
+    );
+    println!(
+        "BVH nodes visited: {} ({:.2} per ray)",
+        stats.bvh_nodes_visited,
+        stats.bvh_nodes_visited_per_ray()
+    );
+    println!("Average path length: {:.2}", stats.average_path_length());
+    println!("{:.2} Mrays/s", progress.rays_per_sec() / 1e6);
+}
@@ -5,6 +5,21 @@ use glam::Vec3;
 
 pub trait Texture: Sync + Send {
     fn value(&self, u: f32, v: f32, p: &Vec3) -> Color;
+
+    /// Mean of the texture over its UV square, what a light using it gives
+    /// off on average. Textures that vary with position should override it.
+    fn average(&self) -> Color {
+        let n = 16;
+        let mut total = Color::ZERO;
+        for i in 0..n {
+            for j in 0..n {
+                let u = (i as f32 + 0.5) / n as f32;
+                let v = (j as f32 + 0.5) / n as f32;
+                total += self.value(u, v, &Vec3::ZERO);
+            }
+        }
+        total / (n * n) as f32
+    }
 }
 
 pub struct SolidTex {
@@ -21,6 +36,10 @@ impl Texture for SolidTex {
     fn value(&self, u: f32, v: f32, p: &Vec3) -> Color {
         self.color
     }
+
+    fn average(&self) -> Color {
+        self.color
+    }
 }
 
 pub struct CheckerTex {
@@ -50,4 +69,9 @@ impl Texture for CheckerTex {
             self.even.value(u, v, p)
         }
     }
+
+    fn average(&self) -> Color {
+        // The sines are negative over half of space
+        (self.even.average() + self.odd.average()) / 2.
+    }
 }
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+/// Rectangle of pixels traced together, rows go top to bottom.
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Order tiles are handed out in.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TileOrder {
+    Scanline,
+    /// From the center of the image outwards
+    Spiral,
+    /// Along a Hilbert curve over the next power of two grid, skipping the
+    /// cells outside the image. Consecutive tiles are neighbors when the
+    /// grid is a power of two square, otherwise the order jumps where the
+    /// curve leaves the image and comes back, but stays mostly local.
+    Hilbert,
+}
+
+/// Splits the image into `size` x `size` tiles, smaller along the right and
+/// bottom edges, in the given order.
+pub fn tiles(width: usize, height: usize, size: usize, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let nx = (width + size - 1) / size;
+    let ny = (height + size - 1) / size;
+
+    let mut coords: Vec<(usize, usize)> = (0..ny)
+        .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+        .collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let cx = (nx as f32 - 1.) / 2.;
+            let cy = (ny as f32 - 1.) / 2.;
+            let key = |&(tx, ty): &(usize, usize)| {
+                let dx = tx as f32 - cx;
+                let dy = ty as f32 - cy;
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            coords.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let n = nx.max(ny).next_power_of_two();
+            coords.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+        }
+    }
+
+    coords
+        .into_iter()
+        .map(|(tx, ty)| Tile {
+            x0: tx * size,
+            y0: ty * size,
+            width: size.min(width - tx * size),
+            height: size.min(height - ty * size),
+        })
+        .collect()
+}
+
+/// Distance along the Hilbert curve filling an `n` x `n` grid, `n` a power
+/// of two.
+fn hilbert_index(n: usize, mut x: usize, mut y: usize) -> usize {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s) > 0;
+        let ry = (y & s) > 0;
+        d += s * s * ((3 * rx as usize) ^ ry as usize);
+        if !ry {
+            if rx {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+/// Prints how long tiles took, with the slowest ones called out since that
+/// is where the expensive parts of the image are.
+pub fn report(tiles: &[Tile], times: &[Duration]) {
+    if tiles.is_empty() {
+        return;
+    }
+    let total: Duration = times.iter().sum();
+    let mean = total / tiles.len() as u32;
+    let min = times.iter().min().unwrap();
+    let max = times.iter().max().unwrap();
+    println!(
+        "{} tiles: min {} ms, mean {} ms, max {} ms",
+        tiles.len(),
+        min.as_millis(),
+        mean.as_millis(),
+        max.as_millis()
+    );
+
+    let mut slowest: Vec<usize> = (0..tiles.len()).collect();
+    slowest.sort_by_key(|&i| std::cmp::Reverse(times[i]));
+    for &i in slowest.iter().take(3) {
+        let tile = tiles[i];
+        println!(
+            "  tile at ({}, {}) took {} ms",
+            tile.x0,
+            tile.y0,
+            times[i].as_millis()
+        );
+    }
+}
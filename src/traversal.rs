@@ -0,0 +1,112 @@
+use bvh::{
+    bvh::{BVHNode, BVH},
+    ray::Ray,
+};
+
+/// Deepest BVH that can be walked, far more than the trees built here get
+const MAX_DEPTH: usize = 64;
+
+/// Walks a BVH like its `traverse_iterator`, yielding the shapes whose
+/// bounds `ray` passes through, while counting the nodes it visits to get
+/// there. Those are what the traversal costs besides the shapes' own tests.
+pub struct Traversal<'a, T> {
+    nodes: &'a [BVHNode],
+    shapes: &'a [T],
+    ray: &'a Ray,
+    stack: [usize; MAX_DEPTH],
+    len: usize,
+    /// Nodes taken off the stack so far, leaves included
+    pub nodes_visited: usize,
+}
+
+impl<'a, T> Traversal<'a, T> {
+    pub fn new(bvh: &'a BVH, ray: &'a Ray, shapes: &'a [T]) -> Self {
+        let mut traversal = Self {
+            nodes: &bvh.nodes,
+            shapes,
+            ray,
+            stack: [0; MAX_DEPTH],
+            len: 0,
+            nodes_visited: 0,
+        };
+        if !traversal.nodes.is_empty() {
+            traversal.push(0);
+        }
+        traversal
+    }
+
+    fn push(&mut self, index: usize) {
+        self.stack[self.len] = index;
+        self.len += 1;
+    }
+}
+
+impl<'a, T> Iterator for Traversal<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        while self.len > 0 {
+            self.len -= 1;
+            self.nodes_visited += 1;
+            match &self.nodes[self.stack[self.len]] {
+                BVHNode::Leaf { shape_index, .. } => return Some(&self.shapes[*shape_index]),
+                BVHNode::Node {
+                    child_l_index,
+                    child_l_aabb,
+                    child_r_index,
+                    child_r_aabb,
+                    ..
+                } => {
+                    // Right first so the left child is visited first
+                    if self.ray.intersects_aabb(child_r_aabb) {
+                        self.push(*child_r_index);
+                    }
+                    if self.ray.intersects_aabb(child_l_aabb) {
+                        self.push(*child_l_index);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bvh::sphere::Sphere;
+    use glam::Vec3;
+
+    use super::*;
+    use crate::{
+        material::{Lambertian, ToWithMat, WithMat},
+        sampler::Pcg32,
+    };
+
+    #[test]
+    fn yields_what_traverse_iterator_does() {
+        let mut rng = Pcg32::new(3, 0);
+        let mut point = || Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 20. - 10.;
+        let mat = Arc::new(Lambertian::new(Vec3::ONE));
+        let mut spheres: Vec<WithMat> = (0..200)
+            .map(|_| Sphere::new(point(), 0.5).with_mat(mat.clone()))
+            .collect();
+        let bvh = BVH::build(&mut spheres);
+
+        for _ in 0..100 {
+            let ray = Ray::new(point(), point());
+            let mut expected: Vec<usize> = bvh
+                .traverse_iterator(&ray, &spheres)
+                .map(|obj| obj.node_index)
+                .collect();
+            let mut traversal = Traversal::new(&bvh, &ray, &spheres);
+            let mut found: Vec<usize> = traversal.by_ref().map(|obj| obj.node_index).collect();
+            expected.sort_unstable();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+            // The root at least, and every leaf that was found
+            assert!(traversal.nodes_visited > found.len());
+        }
+    }
+}
@@ -1,37 +1,265 @@
-use std::time::Instant;
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use bvh::{
     aabb::Bounded,
     bvh::BVH,
     ray::{Intersection, IntersectionRay, Ray},
+    sphere::Sphere,
 };
-use glam::Vec3;
+use glam::{Mat4, Vec3};
 use image::ImageBuffer;
 
 use crate::{
+    adaptive::{self, PixelStats},
+    aov::{Aov, AovBuffers},
+    background::Background,
     camera::Camera,
     color::{Color, RGB},
+    denoise::Denoiser,
+    distribution::AliasTable,
+    film::{Film, Filter},
+    light::Light,
+    integrator::{Integrator, PathTracer, Splat},
+    lpe::Lobe,
     material::{Material, WithMat},
-    random,
+    power_heuristic, rand_unit_vector, random,
+    sampler::{self, SamplerKind},
+    stats::{self, Progress, ProgressTracker},
+    tile::{self, Tile, TileOrder},
+    traversal::Traversal,
 };
 use rayon::prelude::*;
 pub trait Hittable: IntersectionRay + Bounded + Sync + Send {}
 
 impl<T> Hittable for T where T: IntersectionRay + Bounded + Sync + Send {}
 
+/// Point picked on a surface, with the outward normal, the texture
+/// coordinates a hit there would get and the area pdf.
+#[derive(Clone, Copy)]
+pub struct SurfaceSample {
+    pub p: Vec3,
+    pub n: Vec3,
+    pub u: f32,
+    pub v: f32,
+    pub pdf: f32,
+}
+
+/// What sampling emitters and the debug views need to know about a shape
+/// besides how to hit it, used through `with_surface_mat`. Shapes without
+/// it still work with `with_mat`. The defaults mean the shape can't be
+/// sampled, so only what can be computed needs overriding.
+pub trait Surface: Sync + Send {
+    /// Surface area once `transform` is applied, used to weigh emitters by
+    /// the power they give off.
+    fn area(&self, _transform: &Mat4) -> f32 {
+        0.
+    }
+
+    /// Uniformly samples a point on the surface. Shapes that can't be
+    /// sampled return `None` and can only be found as lights by chance.
+    fn sample_surface(&self) -> Option<SurfaceSample> {
+        None
+    }
+
+    /// Area pdf of `sample_surface` picking `p`, which has normal `n`.
+    fn surface_pdf(&self, _p: Vec3, _n: Vec3) -> f32 {
+        0.
+    }
+
+    /// Normal of the surface itself at a hit, before any smoothing, facing
+    /// against `ray`.
+    fn geometric_normal(&self, _ray: &Ray, intersection: &Intersection) -> Vec3 {
+        intersection.norm
+    }
+
+    /// Triangles whose bounds `ray` passes through, each one costing an
+    /// intersection test.
+    fn triangles_tested(&self, _ray: &Ray) -> usize {
+        0
+    }
+}
+
+impl Surface for Sphere {
+    fn area(&self, transform: &Mat4) -> f32 {
+        // Knud Thomsen's approximation for the area of an ellipsoid
+        let aabb = self.aabb();
+        let radius = (aabb.max.x - aabb.min.x) / 2.;
+        let a = radius * transform.x_axis.truncate().length();
+        let b = radius * transform.y_axis.truncate().length();
+        let c = radius * transform.z_axis.truncate().length();
+        let p = 1.6075;
+        let mean = ((a * b).powf(p) + (a * c).powf(p) + (b * c).powf(p)) / 3.;
+        4. * PI * mean.powf(1. / p)
+    }
+
+    fn sample_surface(&self) -> Option<SurfaceSample> {
+        let aabb = self.aabb();
+        let center = (aabb.min + aabb.max) / 2.;
+        let radius = (aabb.max.x - aabb.min.x) / 2.;
+        let n = rand_unit_vector();
+        let p = center + radius * n;
+        // Hit the point from outside to get the same UVs a ray would
+        let (u, v) = self
+            .intersects_ray(&Ray::new(p + radius * n, -n), 0., f32::INFINITY)
+            .map_or((0., 0.), |inter| (inter.u, inter.v));
+        Some(SurfaceSample {
+            p,
+            n,
+            u,
+            v,
+            pdf: 1. / (4. * PI * radius * radius),
+        })
+    }
+
+    fn surface_pdf(&self, _p: Vec3, _n: Vec3) -> f32 {
+        let aabb = self.aabb();
+        let radius = (aabb.max.x - aabb.min.x) / 2.;
+        1. / (4. * PI * radius * radius)
+    }
+}
+
+#[derive(Clone)]
+pub struct RenderSettings {
+    pub height: usize,
+    pub samples_per_px: usize,
+    pub max_bounces: usize,
+    pub integrator: Arc<dyn Integrator>,
+    /// Renders with the same seed and settings come out bit-identical
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    /// Stop sampling a pixel once the standard error of its luminance,
+    /// relative to the luminance, drops below this. 0 samples every pixel
+    /// `samples_per_px` times
+    pub adaptive_threshold: f32,
+    /// Samples every pixel takes before it can be considered converged
+    pub min_samples_per_px: usize,
+    /// Samples a single noisy pixel can take at most, however much of the
+    /// budget the converged ones left over. Never less than `samples_per_px`
+    pub max_samples_per_px: usize,
+    /// Also write `<name>_samples.png` showing how many samples each pixel took
+    pub sample_heatmap: bool,
+    pub filter: Filter,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// Auxiliary passes to write next to the image, as `<name>_<aov>.png`
+    pub aovs: Vec<Aov>,
+    /// Jittered samples averaged into each AOV pixel
+    pub aov_samples: usize,
+    /// Also write every `Lobe` of the image as `<name>_<lobe>.png`. They are
+    /// filtered like the image and add up to it before gamma
+    pub light_paths: bool,
+    /// Also write a denoised `<name>_denoised.png`
+    pub denoise: Option<Denoiser>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            height: 480,
+            samples_per_px: 1000,
+            max_bounces: 50,
+            integrator: Arc::new(PathTracer),
+            seed: 0,
+            sampler: SamplerKind::Random,
+            adaptive_threshold: 0.,
+            min_samples_per_px: 64,
+            max_samples_per_px: 4000,
+            sample_heatmap: false,
+            filter: Filter::Box { radius: 0.5 },
+            tile_size: 32,
+            tile_order: TileOrder::Hilbert,
+            aovs: vec![],
+            aov_samples: 16,
+            light_paths: false,
+            denoise: None,
+        }
+    }
+}
+
+/// `path` with `_<suffix>` added to the file name, before the extension.
+pub fn sibling_path(path: &str, suffix: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("render");
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("png");
+    path.with_file_name(format!("{}_{}.{}", stem, suffix, ext))
+        .to_string_lossy()
+        .into_owned()
+}
+
 pub struct World {
     pub objs: Vec<WithMat>,
+    pub background: Arc<dyn Background>,
+    /// Delta lights, kept apart from `objs` since they can't be hit
+    pub lights: Vec<Arc<dyn Light>>,
+    /// Indices into `objs` of everything that emits light
+    emitters: Vec<usize>,
+    emitter_table: Option<AliasTable>,
     bvh: BVH,
 }
 
 impl World {
     pub fn new(mut objs: Vec<WithMat>) -> Self {
         let bvh = BVH::build(&mut objs);
-        World { objs, bvh }
+        let mut world = World {
+            objs,
+            background: Arc::new(Vec3::ZERO),
+            lights: vec![],
+            emitters: vec![],
+            emitter_table: None,
+            bvh,
+        };
+        world.assign_ids();
+        world.build_emitters();
+        world
     }
 
     pub fn build(&mut self) {
-        self.bvh.rebuild(&mut self.objs)
+        self.bvh.rebuild(&mut self.objs);
+        self.assign_ids();
+        self.build_emitters();
+    }
+
+    /// Numbers objects by position and materials in order of first use, for
+    /// the ID AOVs.
+    fn assign_ids(&mut self) {
+        let mut materials = HashMap::new();
+        for (i, obj) in self.objs.iter_mut().enumerate() {
+            let key = Arc::as_ptr(&obj.mat) as *const u8 as usize;
+            let next = materials.len();
+            obj.object_id = i;
+            obj.material_id = *materials.entry(key).or_insert(next);
+        }
+    }
+
+    /// Collects the emissive objects into an alias table so they are picked
+    /// in proportion to the power they emit.
+    fn build_emitters(&mut self) {
+        let mut powers = vec![];
+        self.emitters.clear();
+        for (i, obj) in self.objs.iter_mut().enumerate() {
+            obj.light_pmf = 0.;
+            let power = obj.average_emission().luminance() * obj.surface.area(&Mat4::IDENTITY) * PI;
+            if power > 0. {
+                self.emitters.push(i);
+                powers.push(power);
+            }
+        }
+
+        self.emitter_table = if powers.is_empty() {
+            None
+        } else {
+            let table = AliasTable::new(&powers);
+            for (light_idx, &obj_idx) in self.emitters.iter().enumerate() {
+                self.objs[obj_idx].light_pmf = table.pmf(light_idx);
+            }
+            Some(table)
+        };
     }
 
     pub fn first_intersection<'a>(
@@ -50,8 +278,11 @@ impl World {
         //         None
         //     }
         // })
-        self.bvh
-            .traverse_iterator(&ray, &self.objs)
+        stats::count_ray();
+        let mut traversal = self.candidates(&ray);
+        let hit = traversal
+            .by_ref()
+            .inspect(|_| stats::count_object_test())
             .fold(None, |hit, obj| {
                 if let Some(inter) = obj.intersects_ray(&ray, t_min, t_max) {
                     if let Some((last_obj, last_inter)) = hit {
@@ -66,88 +297,419 @@ impl World {
                 } else {
                     hit
                 }
-            })
+            });
+        stats::count_bvh_nodes(traversal.nodes_visited);
+        hit
     }
 
-    pub fn render(
+    /// Objects whose bounds `ray` passes through, counting the BVH nodes
+    /// visited to find them.
+    pub(crate) fn candidates<'a>(&'a self, ray: &'a Ray) -> Traversal<'a, WithMat> {
+        Traversal::new(&self.bvh, ray, &self.objs)
+    }
+
+    /// Returns true if anything lies along `ray` closer than `t_max`.
+    pub fn occluded(&self, ray: Ray, t_max: bvh::Real) -> bool {
+        stats::count_shadow_ray();
+        let mut traversal = self.candidates(&ray);
+        let occluded = traversal
+            .by_ref()
+            .inspect(|_| stats::count_object_test())
+            .any(|obj| obj.intersects_ray(&ray, 0.00001, t_max).is_some());
+        stats::count_bvh_nodes(traversal.nodes_visited);
+        occluded
+    }
+
+    pub fn render(&self, path: &str, camera: &dyn Camera, settings: &RenderSettings) {
+        self.render_with_progress(path, camera, settings, &stats::print_progress);
+    }
+
+    /// Like `render`, calling `progress` every time a tile finishes. It is
+    /// called from the tracing threads, so it should return quickly.
+    pub fn render_with_progress(
         &self,
         path: &str,
-        height: usize,
-        origin: Vec3,
-        lookat: Vec3,
-        vfov: f32,
-        background: Color,
-        aspect_ratio: f32,
+        camera: &dyn Camera,
+        settings: &RenderSettings,
+        progress: &(dyn Fn(&Progress) + Sync),
     ) {
-        let width = (height as f32 * aspect_ratio) as usize;
-        let mut pixels = vec![Color::default(); width * height];
-
-        let samples_per_px = 1000;
-        let max_bounces = 50;
-        let aperture = 0.0;
-
-        // Camera
-        // let dist_to_focus = (lookat - origin).length();
-        let dist_to_focus = 10.;
-        let up = Vec3::new(0., 1., 0.);
-        let camera = Camera::new(
-            origin,
-            lookat,
-            up,
-            vfov,
-            aspect_ratio,
-            aperture,
-            dist_to_focus,
-        );
+        let height = settings.height;
+        let width = (height as f32 * camera.aspect_ratio()) as usize;
+        let mut pixels = vec![PixelStats::default(); width * height];
+        let mut film = Film::new(width, height);
+        let mut lobe_films: Vec<Film> = if settings.light_paths {
+            Lobe::ALL.iter().map(|_| Film::new(width, height)).collect()
+        } else {
+            vec![]
+        };
+
+        let adaptive = settings.adaptive_threshold > 0.;
+        let first_pass = if adaptive {
+            settings.min_samples_per_px.min(settings.samples_per_px)
+        } else {
+            settings.samples_per_px
+        };
 
         println!("Begin Tracing");
 
+        let tiles = tile::tiles(width, height, settings.tile_size, settings.tile_order);
+        let tracker = ProgressTracker::new(settings.samples_per_px * pixels.len());
         let now = Instant::now();
-        pixels.par_iter_mut().enumerate().for_each(|(i, px)| {
-            let x = i % width;
-            let y = (height - 1) - (i / width);
-            for _ in 0..samples_per_px {
-                let u = (x as f32 + random()) / (width - 1) as f32;
-                let v = (y as f32 + random()) / (height - 1) as f32;
-                let ray = camera.get_ray(u, v);
-                *px += self.ray_color(&ray, max_bounces, background);
+        let mut tile_times = self.sample_pixels(
+            camera,
+            settings,
+            &tiles,
+            &mut pixels,
+            &mut film,
+            &mut lobe_films,
+            &tracker,
+            progress,
+            |_| first_pass,
+        );
+
+        if adaptive {
+            // Spend what's left of the budget on the pixels that are still noisy,
+            // a round at a time so the error estimates can catch up
+            let max = settings.max_samples_per_px.max(settings.samples_per_px);
+            let mut remaining = (settings.samples_per_px - first_pass) * pixels.len();
+            while remaining > 0 {
+                let round = remaining.min(pixels.len() * 8);
+                let counts = adaptive::allocate(&pixels, settings.adaptive_threshold, round, max);
+                let total: usize = counts.iter().sum();
+                if total == 0 {
+                    break;
+                }
+                let times = self.sample_pixels(
+                    camera,
+                    settings,
+                    &tiles,
+                    &mut pixels,
+                    &mut film,
+                    &mut lobe_films,
+                    &tracker,
+                    progress,
+                    |i| counts[i],
+                );
+                for (total, time) in tile_times.iter_mut().zip(times) {
+                    *total += time;
+                }
+                remaining = remaining.saturating_sub(total);
             }
-        });
+        }
 
         let elapsed = now.elapsed();
+        stats::report(&tracker.finish());
         println!("Done Tracing in {} ms", elapsed.as_millis());
+        tile::report(&tiles, &tile_times);
 
         let image = ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
             let i = (x + (y * width as u32)) as usize;
-            let c = pixels[i];
-            c.to_px(samples_per_px)
+            film.color(i).to_px(1)
         });
 
         image.save(path).expect("Image to save");
         println!("Image written to {}", path);
-    }
 
-    pub fn ray_color(&self, ray: &Ray, depth: usize, background: Color) -> Color {
-        if depth == 0 {
-            return Vec3::ZERO;
+        for (lobe_film, lobe) in lobe_films.iter().zip(Lobe::ALL) {
+            let lobe_path = sibling_path(path, lobe.name());
+            ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+                lobe_film
+                    .color_unclamped((x + y * width as u32) as usize)
+                    .to_px(1)
+            })
+            .save(&lobe_path)
+            .expect("Image to save");
+            println!("{} written to {}", lobe.name(), lobe_path);
+        }
+
+        if settings.sample_heatmap {
+            let counts: Vec<usize> = pixels.iter().map(|px| px.count).collect();
+            let heatmap_path = sibling_path(path, "samples");
+            adaptive::heatmap(&counts, width, height)
+                .save(&heatmap_path)
+                .expect("Image to save");
+            println!("Sample counts written to {}", heatmap_path);
         }
 
-        if let Some((obj, intersection)) = self.first_intersection(*ray, 0.00001, f32::INFINITY) {
-            let emit = obj.emit(
-                intersection.u,
-                intersection.v,
-                &ray.at(intersection.distance),
+        if settings.aovs.is_empty() && settings.denoise.is_none() {
+            return;
+        }
+        let aovs = AovBuffers::render(self, camera, settings, width, height);
+        for aov in &settings.aovs {
+            let aov_path = sibling_path(path, aov.name());
+            aovs.image(*aov).save(&aov_path).expect("Image to save");
+            println!("{} written to {}", aov.name(), aov_path);
+        }
+
+        if let Some(denoiser) = &settings.denoise {
+            let now = Instant::now();
+            let color: Vec<Color> = (0..pixels.len()).map(|i| film.color(i)).collect();
+            let variance: Vec<f32> = pixels.iter().map(|px| px.variance_of_mean()).collect();
+            let denoised = denoiser.denoise(&color, &variance, &aovs);
+            let denoised_path = sibling_path(path, "denoised");
+            ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+                denoised[(x + y * width as u32) as usize].to_px(1)
+            })
+            .save(&denoised_path)
+            .expect("Image to save");
+            println!(
+                "Denoised in {} ms, written to {}",
+                now.elapsed().as_millis(),
+                denoised_path
             );
-            if let Some((child_ray, attenuation, pdf)) = obj.scatter(ray, &intersection) {
-                emit + self.ray_color(&child_ray, depth - 1, background)
-                    * attenuation
-                    * obj.scattering_pdf(&ray, &intersection, &child_ray)
-                    / pdf
+        }
+    }
+
+    /// Traces `samples(i)` more samples for every pixel `i`, splatting them
+    /// into `film`, and each lobe into the matching film of `lobe_films` if
+    /// there are any. Tiles are traced in parallel into their own buffers,
+    /// which get merged in order so the result doesn't depend on scheduling.
+    /// Returns how long each tile took.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn sample_pixels<F>(
+        &self,
+        camera: &dyn Camera,
+        settings: &RenderSettings,
+        tiles: &[Tile],
+        pixels: &mut [PixelStats],
+        film: &mut Film,
+        lobe_films: &mut [Film],
+        tracker: &ProgressTracker,
+        progress: &(dyn Fn(&Progress) + Sync),
+        samples: F,
+    ) -> Vec<Duration>
+    where
+        F: Fn(usize) -> usize + Sync,
+    {
+        let width = film.width;
+        let height = film.height;
+        let snapshot: &[PixelStats] = pixels;
+        let split_lobes = !lobe_films.is_empty();
+        tracker.start_pass(tiles.len());
+        let results: Vec<(Film, Vec<Film>, Vec<Splat>, Vec<PixelStats>, Duration)> = tiles
+            .par_iter()
+            .map(|tile| {
+                let start = Instant::now();
+                // Drop whatever this thread counted outside of a tile
+                stats::take();
+                let mut traced = 0;
+                let new_buffer = || {
+                    Film::padded_tile(&settings.filter, tile.x0, tile.y0, tile.width, tile.height)
+                };
+                let mut buffer = new_buffer();
+                let mut lobe_buffers: Vec<Film> = if split_lobes {
+                    Lobe::ALL.iter().map(|_| new_buffer()).collect()
+                } else {
+                    vec![]
+                };
+                let mut splats = vec![];
+                let mut tile_pixels = Vec::with_capacity(tile.width * tile.height);
+                sampler::set_sampler(settings.sampler.build(
+                    settings.seed,
+                    settings.samples_per_px,
+                    width,
+                ));
+                for row in tile.y0..tile.y0 + tile.height {
+                    for x in tile.x0..tile.x0 + tile.width {
+                        let i = x + row * width;
+                        let y = (height - 1) - row;
+                        let mut px = snapshot[i].clone();
+                        let n = samples(i);
+                        traced += n;
+                        for _ in 0..n {
+                            sampler::start_sample(i, px.count);
+                            let jx = random();
+                            let jy = random();
+                            let u = (x as f32 + jx) / width as f32;
+                            let v = (y as f32 + jy) / height as f32;
+                            let ray = camera.get_ray(u, v);
+                            let lobes = settings.integrator.li_splat(
+                                self,
+                                camera,
+                                &ray,
+                                settings.max_bounces,
+                                &mut splats,
+                            );
+                            let color = lobes.total();
+                            let (fx, fy) = (x as f32 + jx, row as f32 + 1. - jy);
+                            px.add(color);
+                            buffer.add_sample(&settings.filter, fx, fy, color);
+                            buffer.samples += 1;
+                            for (lobe_buffer, lobe) in lobe_buffers.iter_mut().zip(Lobe::ALL) {
+                                lobe_buffer.add_sample(&settings.filter, fx, fy, lobes[lobe]);
+                                lobe_buffer.samples += 1;
+                            }
+                        }
+                        tile_pixels.push(px);
+                    }
+                }
+                progress(&tracker.tile_done(traced, stats::take()));
+                (buffer, lobe_buffers, splats, tile_pixels, start.elapsed())
+            })
+            .collect();
+
+        let mut times = Vec::with_capacity(tiles.len());
+        for (tile, (buffer, lobe_buffers, splats, tile_pixels, elapsed)) in
+            tiles.iter().zip(results)
+        {
+            film.merge(&buffer);
+            for splat in splats {
+                let (x, y) = (splat.u * width as f32, (1. - splat.v) * height as f32);
+                film.add_splat(x, y, splat.color);
+                if let Some(lobe_film) = lobe_films.get_mut(splat.lobe as usize) {
+                    lobe_film.add_splat(x, y, splat.color);
+                }
+            }
+            for (lobe_film, lobe_buffer) in lobe_films.iter_mut().zip(&lobe_buffers) {
+                lobe_film.merge(lobe_buffer);
+            }
+            let mut tile_pixels = tile_pixels.into_iter();
+            for row in tile.y0..tile.y0 + tile.height {
+                for x in tile.x0..tile.x0 + tile.width {
+                    pixels[x + row * width] = tile_pixels.next().unwrap();
+                }
+            }
+            times.push(elapsed);
+        }
+        times
+    }
+
+    /// Direct lighting at a scattering hit from the background, the delta
+    /// lights and one of the emitters. With `mis` the background and emitter
+    /// are weighted against finding them by scattering, which the caller
+    /// then has to do as well.
+    pub(crate) fn direct_light(
+        &self,
+        ray: &Ray,
+        intersection: &Intersection,
+        obj: &WithMat,
+        attenuation: Color,
+        mis: bool,
+    ) -> Color {
+        self.sample_background(ray, intersection, obj, attenuation, mis)
+            + self.sample_lights(ray, intersection, obj, attenuation)
+            + self.sample_emitters(ray, intersection, obj, attenuation, mis)
+    }
+
+    /// Direct lighting from the background at a scattering hit, weighted
+    /// against finding the background by scattering.
+    pub(crate) fn sample_background(
+        &self,
+        ray: &Ray,
+        intersection: &Intersection,
+        obj: &WithMat,
+        attenuation: Color,
+        mis: bool,
+    ) -> Color {
+        if let Some((dir, pdf)) = self.background.sample() {
+            let shadow_ray = Ray::new(ray.at(intersection.distance), dir);
+            let scattering_pdf = obj.scattering_pdf(ray, intersection, &shadow_ray);
+            if scattering_pdf <= 0. || self.occluded(shadow_ray, f32::INFINITY) {
+                return Color::ZERO;
+            }
+            let weight = if mis {
+                power_heuristic(pdf, scattering_pdf)
             } else {
-                emit
+                1.
+            };
+            attenuation * scattering_pdf * self.background.value(dir) * weight / pdf
+        } else {
+            Color::ZERO
+        }
+    }
+
+    /// Direct lighting from every delta light at a scattering hit.
+    pub(crate) fn sample_lights(
+        &self,
+        ray: &Ray,
+        intersection: &Intersection,
+        obj: &WithMat,
+        attenuation: Color,
+    ) -> Color {
+        let hit = ray.at(intersection.distance);
+        let mut color = Color::ZERO;
+        for light in &self.lights {
+            if let Some((dir, dist, li)) = light.sample_li(hit) {
+                let shadow_ray = Ray::new(hit, dir);
+                let scattering_pdf = obj.scattering_pdf(ray, intersection, &shadow_ray);
+                if scattering_pdf > 0. && !self.occluded(shadow_ray, dist) {
+                    color += attenuation * scattering_pdf * li;
+                }
             }
+        }
+        color
+    }
+
+    /// Direct lighting from one emissive object, picked by power, weighted
+    /// against finding it by scattering.
+    fn sample_emitters(
+        &self,
+        ray: &Ray,
+        intersection: &Intersection,
+        obj: &WithMat,
+        attenuation: Color,
+        mis: bool,
+    ) -> Color {
+        let (light, sample) = match self.sample_emitter() {
+            Some(sample) => sample,
+            None => return Color::ZERO,
+        };
+        let (p, n) = (sample.p, sample.n);
+
+        let hit = ray.at(intersection.distance);
+        let to_light = p - hit;
+        let dist = to_light.length();
+        let dir = to_light / dist;
+        let cos_light = n.dot(dir).abs();
+        if cos_light <= 0. {
+            return Color::ZERO;
+        }
+        let pdf = sample.pdf * dist * dist / cos_light;
+
+        let shadow_ray = Ray::new(hit, dir);
+        let scattering_pdf = obj.scattering_pdf(ray, intersection, &shadow_ray);
+        if scattering_pdf <= 0. || self.occluded(shadow_ray, dist * 0.999) {
+            return Color::ZERO;
+        }
+        // Surface samples come with the outward normal, orient it against
+        // the shadow ray like a regular hit
+        let back_face = n.dot(dir) > 0.;
+        let norm = if back_face { -n } else { n };
+        let light_hit = Intersection::new(dist, sample.u, sample.v, norm, back_face);
+        let emit = light.emit(&shadow_ray, &light_hit);
+        let weight = if mis {
+            power_heuristic(pdf, scattering_pdf)
         } else {
-            background
+            1.
+        };
+        attenuation * scattering_pdf * emit * weight / pdf
+    }
+
+    /// Picks an emitter by power and a point on it. The sample's pdf
+    /// includes the chance of picking that emitter.
+    pub(crate) fn sample_emitter(&self) -> Option<(&WithMat, SurfaceSample)> {
+        let table = self.emitter_table.as_ref()?;
+        let (light_idx, pmf) = table.sample(random());
+        let light = &self.objs[self.emitters[light_idx]];
+        let sample = light.surface.sample_surface()?;
+        Some((
+            light,
+            SurfaceSample {
+                pdf: pmf * sample.pdf,
+                ..sample
+            },
+        ))
+    }
+
+    /// Solid angle pdf of `sample_emitters` picking the point where `ray`
+    /// hit `obj`.
+    pub(crate) fn emitter_pdf(&self, ray: &Ray, intersection: &Intersection, obj: &WithMat) -> f32 {
+        let hit = ray.at(intersection.distance);
+        let cos_light = intersection.norm.dot(ray.direction).abs();
+        if cos_light <= 0. {
+            return 0.;
         }
+        let dist = intersection.distance;
+        obj.light_pmf * obj.surface.surface_pdf(hit, intersection.norm) * dist * dist / cos_light
     }
 }